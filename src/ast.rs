@@ -1,6 +1,9 @@
 use core::fmt;
 
-#[derive(PartialEq)]
+use crate::ir::{BinOp, Instr, Operand};
+use crate::scanner::Span;
+
+#[derive(PartialEq, Clone, Copy)]
 pub enum Type {
     Int(i32),
     Float(f32),
@@ -9,8 +12,8 @@ pub enum Type {
 impl fmt::Debug for Type {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Int => write!(f, "i"),
-            Float => write!(f, "f")
+            Type::Int(_)   => write!(f, "i"),
+            Type::Float(_) => write!(f, "f")
         }
     }
 }
@@ -23,9 +26,24 @@ impl Type {
     pub fn is_float(&self) -> bool {
         matches!(*self, Self::Float(_))
     }
+
+    // compares only the kind of the type, not the value it carries
+    pub fn same_type(&self, other: &Type) -> bool {
+        self.is_int() == other.is_int()
+    }
+
+    // the type both operands of a binary operation are converted to,
+    // following C's usual arithmetic conversions for int and float
+    pub fn usual_arithmetic_conversion(&self, other: &Type) -> Type {
+        if self.is_float() || other.is_float() {
+            Type::Float(0.0)
+        } else {
+            Type::Int(0)
+        }
+    }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 pub enum NodeType {
     Num,
     VarID,
@@ -34,8 +52,22 @@ pub enum NodeType {
     Sub,
     Mult,
     Div,
+    Mod,
     Eq,
+    Ne,
     Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    // ~x, kept as x ^ -1
+    BitNot,
+    Shl,
+    Shr,
     IntToFloat,
     FloatToInt,
     IntToVR,
@@ -43,23 +75,69 @@ pub enum NodeType {
     Leaf,
 }
 
-impl fmt::Debug for NodeType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl NodeType {
+    pub fn is_comparison(&self) -> bool {
+        matches!(*self, Self::Eq | Self::Ne | Self::Lt | Self::Gt | Self::Le | Self::Ge)
+    }
+
+    // && and ||, which take the truth value of each side
+    pub fn is_logical(&self) -> bool {
+        matches!(*self, Self::And | Self::Or)
+    }
+
+    // operators C only defines on integers
+    pub fn is_int_only(&self) -> bool {
+        matches!(*self, Self::Mod | Self::BitAnd | Self::BitOr | Self::BitXor | Self::BitNot | Self::Shl | Self::Shr)
+    }
+
+    // how an operator is written in the source
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            NodeType::Add  => "+",
+            NodeType::Sub  => "-",
+            NodeType::Mult => "*",
+            NodeType::Div  => "/",
+            NodeType::Mod  => "%",
+            NodeType::Eq   => "==",
+            NodeType::Ne   => "!=",
+            NodeType::Lt   => "<",
+            NodeType::Gt   => ">",
+            NodeType::Le   => "<=",
+            NodeType::Ge   => ">=",
+            NodeType::And  => "&&",
+            NodeType::Or   => "||",
+            NodeType::BitAnd => "&",
+            NodeType::BitOr  => "|",
+            NodeType::BitXor => "^",
+            NodeType::BitNot => "~",
+            NodeType::Shl  => "<<",
+            NodeType::Shr  => ">>",
+            _ => "",
+        }
+    }
+
+    pub fn bin_op(&self) -> Option<BinOp> {
         match self {
-            Num        => write!(f, "2vr"),
-            VarID      => write!(f, ""),
-            IOID       => write!(f, "2vr"),
-            Add        => write!(f, "add"),
-            Sub        => write!(f, "sub"),
-            Mult       => write!(f, "mult"),
-            Div        => write!(f, "div"),
-            Eq         => write!(f, "eq"),
-            Lt         => write!(f, "lt"),
-            IntToFloat => write!(f, "vr_int2float"),
-            FloatToInt => write!(f, "vr_float2int"),
-            IntToVR    => write!(f, "int2vr"),
-            FloatToVR  => write!(f, "float2vr"),
-            Leaf       => write!(f, "Leaf Node"),
+            NodeType::Add  => Some(BinOp::Add),
+            NodeType::Sub  => Some(BinOp::Sub),
+            NodeType::Mult => Some(BinOp::Mult),
+            NodeType::Div  => Some(BinOp::Div),
+            NodeType::Mod  => Some(BinOp::Mod),
+            NodeType::Eq   => Some(BinOp::Eq),
+            NodeType::Ne   => Some(BinOp::Ne),
+            NodeType::Lt   => Some(BinOp::Lt),
+            NodeType::Gt   => Some(BinOp::Gt),
+            NodeType::Le   => Some(BinOp::Le),
+            NodeType::Ge   => Some(BinOp::Ge),
+            NodeType::And  => Some(BinOp::And),
+            NodeType::Or   => Some(BinOp::Or),
+            NodeType::BitAnd => Some(BinOp::BitAnd),
+            NodeType::BitOr  => Some(BinOp::BitOr),
+            NodeType::BitXor |
+            NodeType::BitNot => Some(BinOp::BitXor),
+            NodeType::Shl  => Some(BinOp::Shl),
+            NodeType::Shr  => Some(BinOp::Shr),
+            _ => None,
         }
     }
 }
@@ -69,7 +147,11 @@ pub struct Node {
     pub node_type: NodeType,
     pub val_type: Type,
     pub children: Vec<Node>,
-    pub vr: Option<String>,
+    pub vr: Option<Operand>,
+    // source name of VarID and IOID leaves
+    pub id: Option<String>,
+    // the token a leaf or operator came from, None for nodes the compiler adds
+    pub span: Option<Span>,
 }
 
 impl Node {
@@ -79,35 +161,105 @@ impl Node {
             val_type,
             children: Vec::new(),
             vr: None,
+            id: None,
+            span: None,
         }
     }
 
-    pub fn set_vr(&mut self, vr: &String) {
-        self.vr = Some(vr.to_owned());
+    pub fn new_id(node_type: NodeType, val_type: Type, id: &str) -> Self {
+        Self {
+            id: Some(id.to_owned()),
+            ..Self::new(node_type, val_type)
+        }
     }
 
-    pub fn three_addr_code(self) -> String {
-        let args: String;
-        // figure out the args first to reduce branches
-        match self.children.len() {
-            0 => args = "".to_string(),
-            1 => args = format!("({:?})", self.children[0]),
-            2 => args = format!("({:?},{:?})", self.children[0], self.children[1]),
-            _ => panic!("This node has {:?} children, not 1 or 2", self.children.len())
+    pub fn set_vr(&mut self, vr: Operand) {
+        self.vr = Some(vr);
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+
+    // the instruction computing this node into its vr, for example
+    // `vr3 = addf(vr1, vr2);`. Must be called after vrs are assigned
+    pub fn three_addr_code(&self) -> Option<Instr> {
+        // variables are already held in a register of their own
+        if self.node_type == NodeType::VarID {
+            return None;
         }
+        let dst = self.vr.clone().expect("three_addr_code called before vrs were assigned");
 
-        // VarID, IOID, and Num are special cases
-        // because int and float are printed differently
-        match self.node_type {
-            NodeType::VarID => "".to_string(),
-            NodeType::IOID  |
-            NodeType::Num   => {
+        // leaves load their value, everything else reads its children's vrs
+        let src = match self.node_type {
+            NodeType::IOID => Operand::IO(self.id.clone().unwrap_or_default()),
+            NodeType::Num  => Operand::Imm(self.val_type),
+            _ => self.child_vr(0),
+        };
+
+        let instr = match self.node_type {
+            NodeType::IOID |
+            NodeType::Num  => {
                 match self.val_type {
-                    Type::Int(_)   => format!("{:?} = int{:?}{:?}", self.vr, self.node_type, args),
-                    Type::Float(_) => format!("{:?} = float{:?}{:?}", self.vr, self.node_type, args),
+                    Type::Int(_)   => Instr::IntToVR { dst, src },
+                    Type::Float(_) => Instr::FloatToVR { dst, src },
                 }
             }
-            _ => format!("{:?} = {:?}{:?}{:?};", self.vr, self.node_type, self.val_type, args)
+            NodeType::IntToFloat => Instr::IntToFloat { dst, src },
+            NodeType::FloatToInt => Instr::FloatToInt { dst, src },
+            _ => match self.node_type.bin_op() {
+                Some(op) => Instr::Binary { op, op_type: self.op_type(), dst, lhs: src, rhs: self.child_vr(1) },
+                None => panic!("{:?} node has no three address code", self.node_type),
+            }
+        };
+
+        Some(instr)
+    }
+
+    // indented dump of the tree, one node per line with its type and vr.
+    // Before type inference only the leaves have a meaningful type
+    pub fn tree(&self, typed: bool) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, 0, typed);
+        out
+    }
+
+    fn write_tree(&self, out: &mut String, depth: usize, typed: bool) {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(&format!("{:?}", self.node_type));
+        match (&self.node_type, &self.id, self.val_type) {
+            (_, Some(id), _) => out.push_str(&format!(" {}", id)),
+            (NodeType::Num, _, t) => out.push_str(&format!(" {}", Operand::Imm(t))),
+            _ => {},
+        }
+        if !typed && !self.children.is_empty() {
+            out.push_str(": ?");
+        } else if self.val_type.is_int() {
+            out.push_str(": int");
+        } else {
+            out.push_str(": float");
+        }
+        if let Some(vr) = &self.vr {
+            out.push_str(&format!(" -> {}", vr));
+        }
+        out.push('\n');
+
+        for child in self.children.iter() {
+            child.write_tree(out, depth + 1, typed);
+        }
+    }
+
+    fn child_vr(&self, i: usize) -> Operand {
+        self.children[i].vr.clone().expect("child has no vr assigned")
+    }
+
+    // the type an operation is carried out in, which for comparisons is the
+    // type of the operands rather than the int they produce
+    pub fn op_type(&self) -> Type {
+        if self.node_type.is_comparison() {
+            self.children[0].val_type
+        } else {
+            self.val_type
         }
     }
 
@@ -117,6 +269,8 @@ impl Node {
             val_type,
             children: Vec::from([ast]),
             vr: None,
+            id: None,
+            span: None,
         }
     }
 }
@@ -0,0 +1,412 @@
+use core::fmt;
+
+// every diagnostic has a code, which keeps its meaning once released so
+// explanations and suppressions can refer to it
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Code {
+    UndeclaredVariable,
+    Redeclared,
+    Syntax,
+    UnsupportedKeyword,
+    IntOnlyOperator,
+    UnexpectedChar,
+    UnterminatedComment,
+    InvalidNumber,
+    NumberOutOfRange,
+    DuplicateParameter,
+    UnusedVariable,
+    UnusedParameter,
+    ReadBeforeAssignment,
+    ImplicitNarrowing,
+    FloatEquality,
+    DivisionByZero,
+    ConstantOverflow,
+    AssignmentInExpression,
+    ExpressionTooDeep,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        self.entry().1
+    }
+
+    // long form explanation with an example, printed by --explain
+    pub fn explanation(&self) -> &'static str {
+        self.entry().2
+    }
+
+    pub fn from_name(name: &str) -> Option<Code> {
+        CODES.iter().find(|e| e.1.eq_ignore_ascii_case(name)).map(|e| e.0)
+    }
+
+    // the name -W and -Wno- know a warning by, and whether it is on when
+    // neither is given
+    pub fn lint(&self) -> Option<(&'static str, bool)> {
+        LINTS.iter().find(|l| l.0 == *self).map(|l| (l.1, l.2))
+    }
+
+    pub fn from_lint(name: &str) -> Option<Code> {
+        LINTS.iter().find(|l| l.1 == name).map(|l| l.0)
+    }
+
+    fn entry(&self) -> &'static (Code, &'static str, &'static str) {
+        CODES.iter().find(|e| e.0 == *self).expect("every code has an entry")
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+static LINTS: [(Code, &str, bool); 7] = [
+    (Code::UnusedVariable, "unused-variable", true),
+    (Code::UnusedParameter, "unused-parameter", true),
+    (Code::ReadBeforeAssignment, "maybe-uninitialized", true),
+    (Code::ImplicitNarrowing, "narrowing", false),
+    (Code::FloatEquality, "float-equal", false),
+    (Code::DivisionByZero, "div-by-zero", true),
+    (Code::ConstantOverflow, "overflow", true),
+];
+
+static CODES: [(Code, &str, &str); 19] = [
+    (Code::UndeclaredVariable, "CM0001", "\
+A variable was used without being declared.
+
+Erroneous code example:
+
+    void f(int &a) {
+        x = a;      // error: `x` was never declared
+    }
+
+Every local has to be declared with its type, in the same block or an
+enclosing one, before it is assigned or read:
+
+    void f(int &a) {
+        int x;
+        x = a;
+    }
+"),
+    (Code::Redeclared, "CM0002", "\
+A name was declared twice in the same scope.
+
+Erroneous code example:
+
+    void f(int &a) {
+        int x;
+        float x;    // error: `x` is already declared in this block
+    }
+
+Each declaration in a block needs its own name. Pick a different one, or
+drop the second declaration if the first one was meant:
+
+    void f(int &a) {
+        int x;
+        float y;
+    }
+"),
+    (Code::Syntax, "CM0003", "\
+The parser found a token that can't appear where it is.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = 1       // error: expected SEMI, found `}`
+    }
+
+The message lists the tokens that could have come next. A missing `;` is
+reported just after the statement it should end:
+
+    void f(int &a) {
+        a = 1;
+    }
+"),
+    (Code::UnsupportedKeyword, "CM0004", "\
+A C keyword that C-Mini does not implement was used.
+
+Erroneous code example:
+
+    void f(int &a) {
+        while (a < 10) {    // error: unsupported keyword `while`
+            a = a + 1;
+        }
+    }
+
+C-Mini supports `if`, `else`, `for`, `int`, `float` and `void`. The rest of
+C's keywords are reserved, so they can't be used as names either. A `while`
+loop can be written as a `for` loop:
+
+    void f(int &a) {
+        for (a = a; a < 10; a++) {
+        }
+    }
+"),
+    (Code::IntOnlyOperator, "CM0005", "\
+An operator that only works on ints was given a float.
+
+Erroneous code example:
+
+    void f(float &b) {
+        int x;
+        x = b % 2;      // error: `%` needs int operands
+    }
+
+`%`, `&`, `|`, `^`, `<<`, `>>` and `~` are only defined on ints. Assign the
+float to an int first, which truncates it:
+
+    void f(float &b) {
+        int x;
+        x = b;
+        x = x % 2;
+    }
+
+That assignment is itself reported under `-Wnarrowing` (see CM0014). C-Mini
+has no casts, so `-Wno-narrowing` is the only way to silence it.
+"),
+    (Code::UnexpectedChar, "CM0006", "\
+The input contains a character no token starts with.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = a $ 2;      // error: unexpected character '$'
+    }
+
+Only the characters of C-Mini's operators, identifiers, numbers and
+whitespace can appear outside a comment.
+"),
+    (Code::UnterminatedComment, "CM0007", "\
+A block comment is never closed.
+
+Erroneous code example:
+
+    void f(int &a) {
+        /* set a
+        a = 1;
+    }               // error: the comment runs to the end of the file
+
+Close the comment with `*/`:
+
+    void f(int &a) {
+        /* set a */
+        a = 1;
+    }
+"),
+    (Code::InvalidNumber, "CM0008", "\
+A number does not follow C's literal syntax.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = 09;         // error: 9 is not an octal digit
+        a = 1e;         // error: the exponent has no digits
+    }
+
+Integers are decimal, octal with a leading `0` or hex with a leading `0x`,
+optionally followed by `u` and `l` suffixes. Floats need a `.` or an
+exponent, as in `1.`, `.5`, `1e-3` or `0x1p4`, optionally followed by `f`
+or `l`.
+"),
+    (Code::NumberOutOfRange, "CM0009", "\
+A literal is too large for its type.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = 4294967296;     // error: does not fit in an int
+    }
+
+Integer literals have to fit in a 32 bit int, up to 2147483647, and float
+literals in a 32 bit float.
+"),
+    (Code::DuplicateParameter, "CM0010", "\
+Two parameters of a function have the same name.
+
+Erroneous code example:
+
+    void f(int &a, float &a) {  // error: `a` is declared twice
+    }
+
+Parameters are IO arguments, read and written by name, so each one needs a
+name of its own:
+
+    void f(int &a, float &b) {
+    }
+"),
+    (Code::UnusedVariable, "CM0011", "\
+A local is declared but its value is never read.
+
+Erroneous code example:
+
+    void f(int &a) {
+        int x;      // warning: `x` is never read
+        x = 2;
+        a = 1;
+    }
+
+This is a warning. Assigning a local doesn't count as using it, since the
+value goes nowhere. Either use the value or remove the local:
+
+    void f(int &a) {
+        int x;
+        x = 2;
+        a = x;
+    }
+"),
+    (Code::UnusedParameter, "CM0012", "\
+An IO argument is neither read nor written by the function.
+
+Erroneous code example:
+
+    void f(int &a, int &b) {    // warning: `b` is never used
+        a = 1;
+    }
+
+This is a warning. A parameter the function never touches is usually a
+mistake in the body or a leftover in the signature. Use it or remove it:
+
+    void f(int &a) {
+        a = 1;
+    }
+"),
+    (Code::ReadBeforeAssignment, "CM0013", "\
+A local may be read before anything has been assigned to it.
+
+Erroneous code example:
+
+    void f(int &a) {
+        int x;
+        if (a) {
+            x = 1;
+        }
+        a = x;      // warning: `x` is unassigned when `a` is zero
+    }
+
+This is a warning. Locals are not initialised, so on the path that skips
+the assignment the read gets whatever the register held. Assign the local
+on every path before reading it:
+
+    void f(int &a) {
+        int x;
+        x = 0;
+        if (a) {
+            x = 1;
+        }
+        a = x;
+    }
+"),
+    (Code::ImplicitNarrowing, "CM0014", "\
+A float is assigned to an int, which drops its fractional part.
+
+Erroneous code example, with `-Wnarrowing`:
+
+    void f(int &a, float &b) {
+        a = b * 2;      // warning: the product is truncated to an int
+    }
+
+This warning is off by default. The conversion truncates towards zero and
+is out of range for floats beyond an int. Every float assigned to an int is
+reported, an int local in between included. C-Mini has no casts to mark the
+truncation as intended, so where it is, `-Wno-narrowing` is the only way to
+silence the warning. Otherwise keep the value a float:
+
+    void f(float &a, float &b) {
+        a = b * 2;
+    }
+"),
+    (Code::FloatEquality, "CM0015", "\
+Two floats are compared with `==` or `!=`.
+
+Erroneous code example, with `-Wfloat-equal`:
+
+    void f(int &a, float &b) {
+        if (b * 3 == 0.3) {     // warning: rounding makes this unlikely
+            a = 1;
+        }
+    }
+
+This warning is off by default. Float arithmetic rounds, so values that are
+equal on paper often differ in their last bits. Compare the difference
+against a tolerance instead:
+
+    void f(int &a, float &b) {
+        if (b * 3 - 0.3 < 0.0001 && 0.3 - b * 3 < 0.0001) {
+            a = 1;
+        }
+    }
+"),
+    (Code::DivisionByZero, "CM0016", "\
+An int is divided by a literal zero.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = a / 0;      // warning: traps when it runs
+    }
+
+Int division and remainder by zero are undefined and trap on most machines.
+Float division by zero is defined, giving an infinity or NaN, so it is not
+reported.
+"),
+    (Code::ConstantOverflow, "CM0017", "\
+An int expression made of literals overflows.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = 2147483647 + 1;     // warning: wraps to -2147483648
+    }
+
+Ints are 32 bits wide, so the result of `+`, `-`, `*`, `/` and `<<` has to
+lie between -2147483648 and 2147483647, and a shift count between 0 and 31.
+Use a float if the value is meant to be that large:
+
+    void f(float &a) {
+        a = 2147483647.0 + 1;
+    }
+"),
+    (Code::AssignmentInExpression, "CM0018", "\
+An assignment, `++` or `--` was used inside an expression.
+
+Erroneous code example:
+
+    void f(int &a, int &b) {
+        a = b++;        // error: `++` can't be used inside an expression
+        if (a = 1) {    // error: `=` can't be used inside an expression
+            b = 2;
+        }
+    }
+
+C-Mini expressions have no side effects. `=`, `+=`, `-=`, `*=`, `/=`, `++`
+and `--` only make statements of their own, so split the expression and
+compare with `==`:
+
+    void f(int &a, int &b) {
+        a = b;
+        b++;
+        if (a == 1) {
+            b = 2;
+        }
+    }
+"),
+    (Code::ExpressionTooDeep, "CM0019", "\
+An expression nests more than 256 operators deep, or more than 64
+parentheses and unary operators deep.
+
+Erroneous code example:
+
+    void f(int &a) {
+        a = a + a + a + ... + a;    // error: 300 additions, one inside the next
+    }
+
+A chain of operators nests too, since `a + b + c` is `(a + b) + c`. Split
+the expression across statements, keeping the parts in locals:
+
+    void f(int &a) {
+        int x;
+        x = a + a + ... + a;        // the first 150 terms
+        a = x + a + ... + a;        // the rest
+    }
+"),
+];
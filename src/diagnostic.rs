@@ -0,0 +1,254 @@
+use core::fmt;
+
+use crate::codes::Code;
+use crate::scanner::{LexError, Span};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error   => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// a span pointed at alongside the primary one, like an earlier declaration
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// a fix: replacing the text under `span` with `replacement`
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    // where the problem is, underlined with ^ and optionally labelled
+    pub span: Span,
+    pub label: Option<String>,
+    // underlined with -
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            span,
+            label: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn error(code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Error, code, message, span)
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(mut self, span: Span, replacement: impl Into<String>, message: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion { span, replacement: replacement.into(), message: message.into() });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // rustc style, the source line of every span with the span underlined:
+    //
+    //     error[CM0001]: use of undeclared variable `y`
+    //      --> test.c:3:9
+    //       |
+    //     3 |     x = y;
+    //       |         ^ not declared in this scope
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut marks: Vec<(Span, char, &str)> = Vec::from([(self.span, '^', self.label.as_deref().unwrap_or_default())]);
+        marks.extend(self.secondary.iter().map(|l| (l.span, '-', l.message.as_str())));
+        // marks on the same line are underlined left to right
+        marks.sort_by_key(|m| (m.0.line, m.0.col));
+
+        let width = marks.iter().map(|m| m.0.line).max().unwrap_or(1).to_string().len();
+        let pad = " ".repeat(width);
+
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        out.push_str(&format!("{}--> {}:{}:{}\n", pad, path, self.span.line, self.span.col));
+        out.push_str(&format!("{} |\n", pad));
+
+        let mut last_line = 0;
+        for (span, mark, message) in marks {
+            let text = source.lines().nth(span.line - 1).unwrap_or_default();
+            if span.line != last_line {
+                out.push_str(&format!("{:>width$} | {}\n", span.line, text.replace('\t', "    ")));
+                last_line = span.line;
+            }
+
+            // the underline stops at the end of the line
+            let start = (span.col - 1).min(text.len());
+            let end = (start + span.end - span.start).min(text.len());
+            let indent = display_width(text.get(..start).unwrap_or_default());
+            let len = display_width(text.get(start..end).unwrap_or_default()).max(1);
+            let underline = mark.to_string().repeat(len);
+            out.push_str(format!("{} | {}{} {}", pad, " ".repeat(indent), underline, message).trim_end());
+            out.push('\n');
+        }
+
+        if !self.notes.is_empty() || !self.suggestions.is_empty() {
+            out.push_str(&format!("{} |\n", pad));
+        }
+        for note in self.notes.iter() {
+            out.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+        for suggestion in self.suggestions.iter() {
+            out.push_str(&format!("{} = help: {}\n", pad, suggestion.message));
+        }
+
+        out
+    }
+
+    // a single line JSON object, for tools. Lines and columns count from 1
+    // and the end of a span is the position just past it
+    pub fn to_json(&self, path: &str, source: &str) -> String {
+        let labels: Vec<String> = self.secondary.iter()
+            .map(|l| format!("{{\"message\":{},{}}}", json_string(&l.message), json_span(l.span, source)))
+            .collect();
+        let notes: Vec<String> = self.notes.iter().map(|n| json_string(n)).collect();
+        let suggestions: Vec<String> = self.suggestions.iter()
+            .map(|s| format!("{{\"message\":{},\"replacement\":{},{}}}",
+                             json_string(&s.message), json_string(&s.replacement), json_span(s.span, source)))
+            .collect();
+
+        format!("{{\"severity\":\"{}\",\"code\":\"{}\",\"message\":{},\"file\":{},{},\"label\":{},\"labels\":[{}],\"notes\":[{}],\"suggestions\":[{}]}}",
+                self.severity,
+                self.code,
+                json_string(&self.message),
+                json_string(path),
+                json_span(self.span, source),
+                self.label.as_deref().map_or("null".to_owned(), json_string),
+                labels.join(","),
+                notes.join(","),
+                suggestions.join(","))
+    }
+}
+
+fn json_span(span: Span, source: &str) -> String {
+    let (end_line, end_col) = line_col(source, span.end);
+    format!("\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}", span.line, span.col, end_line, end_col)
+}
+
+// line and column of the byte `offset` into `source`
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = source.get(..offset).unwrap_or(source);
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, col)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// columns taken up by `text`, with tabs shown as four spaces
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+impl From<LexError> for Diagnostic {
+    fn from(err: LexError) -> Self {
+        let (code, label) = match err {
+            LexError::UnexpectedChar { .. } => (Code::UnexpectedChar, "no token starts with this character"),
+            LexError::UnterminatedComment { .. } => (Code::UnterminatedComment, "comment opened here is never closed"),
+            LexError::InvalidNumber { .. } => (Code::InvalidNumber, "not a valid C literal"),
+            LexError::NumberOutOfRange { .. } => (Code::NumberOutOfRange, "does not fit in its type"),
+        };
+        Diagnostic::error(code, err.to_string(), err.span()).with_label(label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_escapes_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(json_string("tab\tline\nret\r"), "\"tab\\tline\\nret\\r\"");
+        assert_eq!(json_string("bell\u{7}"), "\"bell\\u0007\"");
+    }
+
+    #[test]
+    fn json_span_ends_past_the_last_character() {
+        let source = "int x;\n/* a\n b */";
+        let span = Span { start: 7, end: source.len(), line: 2, col: 1 };
+        let diag = Diagnostic::error(Code::UnterminatedComment, "comment `/*` never closed", span)
+            .with_label("opened here")
+            .with_note("a \"note\"")
+            .with_suggestion(Span { start: 6, end: 6, line: 1, col: 7 }, ";\n", "insert");
+        assert_eq!(diag.to_json("dir\\t.c", source), concat!(
+            "{\"severity\":\"error\",\"code\":\"CM0007\",\"message\":\"comment `/*` never closed\",",
+            "\"file\":\"dir\\\\t.c\",\"line\":2,\"column\":1,\"end_line\":3,\"end_column\":6,",
+            "\"label\":\"opened here\",\"labels\":[],\"notes\":[\"a \\\"note\\\"\"],",
+            "\"suggestions\":[{\"message\":\"insert\",\"replacement\":\";\\n\",",
+            "\"line\":1,\"column\":7,\"end_line\":1,\"end_column\":7}]}"));
+    }
+
+    #[test]
+    fn json_secondary_labels_and_missing_label() {
+        let source = "void f(int &a, float &a) {}";
+        let diag = Diagnostic::error(Code::DuplicateParameter, "duplicate", Span { start: 22, end: 23, line: 1, col: 23 })
+            .with_secondary(Span { start: 12, end: 13, line: 1, col: 13 }, "first");
+        assert_eq!(diag.to_json("t.c", source), concat!(
+            "{\"severity\":\"error\",\"code\":\"CM0010\",\"message\":\"duplicate\",\"file\":\"t.c\",",
+            "\"line\":1,\"column\":23,\"end_line\":1,\"end_column\":24,\"label\":null,",
+            "\"labels\":[{\"message\":\"first\",\"line\":1,\"column\":13,\"end_line\":1,\"end_column\":14}],",
+            "\"notes\":[],\"suggestions\":[]}"));
+    }
+}
@@ -0,0 +1,189 @@
+use core::fmt;
+use std::ops::Range;
+
+use crate::ast::Type;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Operand {
+    // virtual register allocated by the compiler
+    VR(usize),
+    // local variable, which also lives in a virtual register
+    Var(String),
+    // function argument, only reachable through the 2vr/vr2 conversions
+    IO(String),
+    Imm(Type),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::VR(n)              => write!(f, "vr{}", n),
+            Operand::Var(name)          => write!(f, "{}", name),
+            Operand::IO(name)           => write!(f, "{}", name),
+            Operand::Imm(Type::Int(i))   => write!(f, "{}", i),
+            Operand::Imm(Type::Float(x)) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mult,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    // logical, both sides are always evaluated
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinOp::Add  => write!(f, "add"),
+            BinOp::Sub  => write!(f, "sub"),
+            BinOp::Mult => write!(f, "mult"),
+            BinOp::Div  => write!(f, "div"),
+            BinOp::Mod  => write!(f, "mod"),
+            BinOp::Eq   => write!(f, "eq"),
+            BinOp::Ne   => write!(f, "ne"),
+            BinOp::Lt   => write!(f, "lt"),
+            BinOp::Gt   => write!(f, "gt"),
+            BinOp::Le   => write!(f, "le"),
+            BinOp::Ge   => write!(f, "ge"),
+            BinOp::And  => write!(f, "and"),
+            BinOp::Or   => write!(f, "or"),
+            BinOp::BitAnd => write!(f, "band"),
+            BinOp::BitOr  => write!(f, "bor"),
+            BinOp::BitXor => write!(f, "bxor"),
+            BinOp::Shl  => write!(f, "shl"),
+            BinOp::Shr  => write!(f, "shr"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Instr {
+    // `op_type` is the type the operation is carried out in, comparisons
+    // always produce an int
+    Binary { op: BinOp, op_type: Type, dst: Operand, lhs: Operand, rhs: Operand },
+    IntToFloat { dst: Operand, src: Operand },
+    FloatToInt { dst: Operand, src: Operand },
+    // loads an immediate or IO argument into a vr
+    IntToVR { dst: Operand, src: Operand },
+    FloatToVR { dst: Operand, src: Operand },
+    // stores a vr into an IO argument
+    VRToInt { dst: Operand, src: Operand },
+    VRToFloat { dst: Operand, src: Operand },
+    Copy { dst: Operand, src: Operand },
+    Label(String),
+    Beq { lhs: Operand, rhs: Operand, label: String },
+    #[allow(dead_code)]
+    Bne { lhs: Operand, rhs: Operand, label: String },
+    Branch(String),
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Binary { op, op_type, dst, lhs, rhs } => write!(f, "{} = {}{:?}({}, {});", dst, op, op_type, lhs, rhs),
+            Instr::IntToFloat { dst, src } => write!(f, "{} = vr_int2float({});", dst, src),
+            Instr::FloatToInt { dst, src } => write!(f, "{} = vr_float2int({});", dst, src),
+            Instr::IntToVR { dst, src }    => write!(f, "{} = int2vr({});", dst, src),
+            Instr::FloatToVR { dst, src }  => write!(f, "{} = float2vr({});", dst, src),
+            Instr::VRToInt { dst, src }    => write!(f, "{} = vr2int({});", dst, src),
+            Instr::VRToFloat { dst, src }  => write!(f, "{} = vr2float({});", dst, src),
+            Instr::Copy { dst, src }       => write!(f, "{} = {};", dst, src),
+            Instr::Label(label)            => write!(f, "{}:", label),
+            Instr::Beq { lhs, rhs, label } => write!(f, "beq({}, {}, {});", lhs, rhs, label),
+            Instr::Bne { lhs, rhs, label } => write!(f, "bne({}, {}, {});", lhs, rhs, label),
+            Instr::Branch(label)           => write!(f, "branch({});", label),
+        }
+    }
+}
+
+impl Instr {
+    // the operands the instruction reads
+    pub fn reads(&self) -> Vec<&Operand> {
+        match self {
+            Instr::Binary { lhs, rhs, .. } |
+            Instr::Beq { lhs, rhs, .. } |
+            Instr::Bne { lhs, rhs, .. } => Vec::from([lhs, rhs]),
+            Instr::IntToFloat { src, .. } |
+            Instr::FloatToInt { src, .. } |
+            Instr::IntToVR { src, .. } |
+            Instr::FloatToVR { src, .. } |
+            Instr::VRToInt { src, .. } |
+            Instr::VRToFloat { src, .. } |
+            Instr::Copy { src, .. } => Vec::from([src]),
+            Instr::Label(_) | Instr::Branch(_) => Vec::new(),
+        }
+    }
+
+    // the operand the instruction writes, if any
+    pub fn writes(&self) -> Option<&Operand> {
+        match self {
+            Instr::Binary { dst, .. } |
+            Instr::IntToFloat { dst, .. } |
+            Instr::FloatToInt { dst, .. } |
+            Instr::IntToVR { dst, .. } |
+            Instr::FloatToVR { dst, .. } |
+            Instr::VRToInt { dst, .. } |
+            Instr::VRToFloat { dst, .. } |
+            Instr::Copy { dst, .. } => Some(dst),
+            Instr::Label(_) | Instr::Beq { .. } | Instr::Bne { .. } | Instr::Branch(_) => None,
+        }
+    }
+}
+
+pub struct Program {
+    // every register the code uses, declared before the first instruction
+    pub declarations: Vec<Operand>,
+    pub code: Vec<Instr>,
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for d in self.declarations.iter() {
+            writeln!(f, "virtual_reg {};", d)?;
+        }
+        for i in self.code.iter() {
+            writeln!(f, "{}", i)?;
+        }
+        Ok(())
+    }
+}
+
+// splits code into basic blocks, given as ranges of instructions. A label
+// starts a new block and a branch ends the current one
+pub fn basic_blocks(code: &[Instr]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (i, instr) in code.iter().enumerate() {
+        if matches!(instr, Instr::Label(_)) && i > start {
+            blocks.push(start..i);
+            start = i;
+        }
+        if matches!(instr, Instr::Beq { .. } | Instr::Bne { .. } | Instr::Branch(_)) {
+            blocks.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    if start < code.len() {
+        blocks.push(start..code.len());
+    }
+
+    blocks
+}
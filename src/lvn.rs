@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use crate::ir::{basic_blocks, BinOp, Instr, Operand};
+
+struct ValueNumbering {
+    counter: usize,
+    // the value number currently held by each operand
+    numbers: HashMap<String, usize>,
+    // expression to its value number and the operand it was computed into
+    exprs: HashMap<String, (usize, Operand)>,
+}
+
+impl ValueNumbering {
+    fn new() -> Self {
+        Self {
+            counter: 0,
+            numbers: HashMap::new(),
+            exprs: HashMap::new(),
+        }
+    }
+
+    fn mk_new_number(&mut self) -> usize {
+        self.counter += 1;
+        self.counter - 1
+    }
+
+    // equal immediates share a number, any other operand gets a fresh one the
+    // first time it is read
+    fn number(&mut self, operand: &Operand) -> usize {
+        let key = operand_key(operand);
+        if let Some(n) = self.numbers.get(&key) {
+            return *n;
+        }
+        let n = self.mk_new_number();
+        self.numbers.insert(key, n);
+        n
+    }
+
+    fn set_number(&mut self, operand: &Operand, n: usize) {
+        self.numbers.insert(operand_key(operand), n);
+    }
+
+    // an earlier operand still holding the value of `expr`, if any
+    fn lookup(&self, expr: &str) -> Option<(usize, Operand)> {
+        let (n, holder) = self.exprs.get(expr)?;
+        if self.numbers.get(&operand_key(holder)) == Some(n) {
+            Some((*n, holder.clone()))
+        } else {
+            None
+        }
+    }
+
+    // numbers `dst = expr`, turning it into a copy when the value is already
+    // available in another operand, or dropping it when `dst` holds it
+    fn compute(&mut self, expr: String, instr: Instr, dst: Operand) -> Option<Instr> {
+        if let Some((n, holder)) = self.lookup(&expr) {
+            if holder == dst {
+                return None;
+            }
+            self.set_number(&dst, n);
+            return Some(Instr::Copy { dst, src: holder });
+        }
+        let n = self.mk_new_number();
+        self.set_number(&dst, n);
+        self.exprs.insert(expr, (n, dst));
+        Some(instr)
+    }
+
+    // the instruction to emit in place of `instr`, if any
+    fn number_instr(&mut self, instr: Instr) -> Option<Instr> {
+        match instr {
+            Instr::Binary { op, op_type, ref dst, ref lhs, ref rhs } => {
+                let mut l = self.number(lhs);
+                let mut r = self.number(rhs);
+                if is_commutative(op) && l > r {
+                    std::mem::swap(&mut l, &mut r);
+                }
+                let expr = format!("{}{:?}({}, {})", op, op_type, l, r);
+                let dst = dst.clone();
+                self.compute(expr, instr, dst)
+            },
+            Instr::IntToFloat { ref dst, ref src } |
+            Instr::FloatToInt { ref dst, ref src } |
+            Instr::IntToVR { ref dst, ref src } |
+            Instr::FloatToVR { ref dst, ref src } => {
+                let n = self.number(src);
+                let expr = match instr {
+                    Instr::IntToFloat { .. } => format!("vr_int2float({})", n),
+                    Instr::FloatToInt { .. } => format!("vr_float2int({})", n),
+                    Instr::IntToVR { .. }    => format!("int2vr({})", n),
+                    _                        => format!("float2vr({})", n),
+                };
+                let dst = dst.clone();
+                self.compute(expr, instr, dst)
+            },
+            // storing to an IO argument gives it a value nothing else is known to hold
+            Instr::VRToInt { ref dst, .. } |
+            Instr::VRToFloat { ref dst, .. } => {
+                let n = self.mk_new_number();
+                self.set_number(dst, n);
+                Some(instr)
+            },
+            Instr::Copy { ref dst, ref src } => {
+                let n = self.number(src);
+                self.set_number(dst, n);
+                Some(instr)
+            },
+            Instr::Label(_) |
+            Instr::Beq { .. } |
+            Instr::Bne { .. } |
+            Instr::Branch(_) => Some(instr),
+        }
+    }
+}
+
+// immediates are keyed by their value as well as their type
+fn operand_key(operand: &Operand) -> String {
+    match operand {
+        Operand::Imm(t) => format!("{:?}{}", t, operand),
+        _ => format!("{:?}", operand),
+    }
+}
+
+fn is_commutative(op: BinOp) -> bool {
+    matches!(op, BinOp::Add | BinOp::Mult | BinOp::Eq | BinOp::Ne | BinOp::And | BinOp::Or |
+                 BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor)
+}
+
+// local value numbering: within each basic block, an expression that was
+// already computed is replaced by a copy of the operand holding its value,
+// or removed when its destination already holds it
+pub fn lvn(code: Vec<Instr>) -> Vec<Instr> {
+    let mut optimized = Vec::new();
+    for block in basic_blocks(&code) {
+        let mut vn = ValueNumbering::new();
+        optimized.extend(code[block].iter().cloned().filter_map(|i| vn.number_instr(i)));
+    }
+
+    optimized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Type;
+
+    fn vr(n: usize) -> Operand {
+        Operand::VR(n)
+    }
+
+    fn add(dst: usize, lhs: Operand, rhs: Operand) -> Instr {
+        Instr::Binary { op: BinOp::Add, op_type: Type::Int(0), dst: vr(dst), lhs, rhs }
+    }
+
+    fn sub(dst: usize, lhs: Operand, rhs: Operand) -> Instr {
+        Instr::Binary { op: BinOp::Sub, op_type: Type::Int(0), dst: vr(dst), lhs, rhs }
+    }
+
+    fn load(dst: usize, io: &str) -> Instr {
+        Instr::IntToVR { dst: vr(dst), src: Operand::IO(io.to_owned()) }
+    }
+
+    #[test]
+    fn commutative_operands_are_canonicalised() {
+        let code = Vec::from([add(2, vr(0), vr(1)), add(3, vr(1), vr(0))]);
+        assert_eq!(lvn(code), [add(2, vr(0), vr(1)), Instr::Copy { dst: vr(3), src: vr(2) }]);
+    }
+
+    #[test]
+    fn non_commutative_operands_keep_their_order() {
+        let code = Vec::from([sub(2, vr(0), vr(1)), sub(3, vr(1), vr(0))]);
+        assert_eq!(lvn(code.clone()), code);
+    }
+
+    #[test]
+    fn redefined_operand_is_not_reused() {
+        let x = Operand::Var("x".to_owned());
+        let code = Vec::from([
+            add(2, x.clone(), vr(1)),
+            Instr::Copy { dst: x.clone(), src: vr(2) },
+            add(3, x.clone(), vr(1)),
+        ]);
+        assert_eq!(lvn(code.clone()), code);
+    }
+
+    #[test]
+    fn overwritten_holder_is_not_reused() {
+        let code = Vec::from([
+            add(2, vr(0), vr(1)),
+            Instr::Copy { dst: vr(2), src: vr(5) },
+            add(3, vr(0), vr(1)),
+        ]);
+        assert_eq!(lvn(code.clone()), code);
+    }
+
+    #[test]
+    fn io_store_invalidates_loads() {
+        let code = Vec::from([
+            load(0, "a"),
+            load(1, "a"),
+            Instr::VRToInt { dst: Operand::IO("a".to_owned()), src: vr(5) },
+            load(2, "a"),
+        ]);
+        assert_eq!(lvn(code), [
+            load(0, "a"),
+            Instr::Copy { dst: vr(1), src: vr(0) },
+            Instr::VRToInt { dst: Operand::IO("a".to_owned()), src: vr(5) },
+            load(2, "a"),
+        ]);
+    }
+
+    #[test]
+    fn recomputation_into_its_holder_is_dropped() {
+        let one = Instr::IntToVR { dst: vr(4), src: Operand::Imm(Type::Int(1)) };
+        let code = Vec::from([one.clone(), add(5, vr(0), vr(4)), one.clone(), add(6, vr(5), vr(4))]);
+        assert_eq!(lvn(code), [one, add(5, vr(0), vr(4)), add(6, vr(5), vr(4))]);
+    }
+
+    #[test]
+    fn values_do_not_cross_blocks() {
+        let code = Vec::from([
+            add(2, vr(0), vr(1)),
+            Instr::Label("label0".to_owned()),
+            add(3, vr(0), vr(1)),
+        ]);
+        assert_eq!(lvn(code.clone()), code);
+    }
+}
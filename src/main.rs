@@ -3,70 +3,410 @@ extern crate lazy_static;
 
 use std::env;
 use std::fs;
-// use std::fs::File;
+use std::io::{self, Write};
+use std::process;
 use scanner::Scanner;
+use parser::{AstStage, Parser};
+use ast::Type;
+use diagnostic::{Diagnostic, Severity};
+use codes::Code;
 
 pub mod parser;
 pub mod scanner;
 pub mod ast;
+pub mod ir;
+pub mod lvn;
+pub mod diagnostic;
+pub mod codes;
+pub mod semantic;
+
+// usage errors are kept apart from errors in the program being compiled
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "\
+Usage: c-mini [options] <file>
+
+Compiles a C-Mini file to three-address code. Use - to read from stdin.
+
+Options:
+  -o <file>    Write the output to <file> instead of stdout
+  -c           Optimise with local value numbering
+  -uf <n>      Unroll for loops by a factor of <n> (default 1)
+  --emit=<stage>
+               Stop after <stage> and print it, one of tokens, ast,
+               typed-ast, ir or ir-opt (default ir, or ir-opt with -c)
+  --error-format=<format>
+               Print errors as human (default) or json, one object per line
+  --explain <code>
+               Print a detailed explanation of an error code
+  -W<name>     Turn on the warning <name>
+  -Wno-<name>  Turn off the warning <name>
+  -Werror      Treat warnings as errors
+  -h, --help   Print this message
+  --version    Print the compiler version
+
+Warnings, on by default unless marked (off):
+  unused-variable, unused-parameter, maybe-uninitialized,
+  narrowing (off), float-equal (off), div-by-zero, overflow
+";
+
+// the stages the driver can stop after
+#[derive(PartialEq, Clone, Copy)]
+enum Emit {
+    Tokens,
+    Ast,
+    TypedAst,
+    Ir,
+    IrOpt,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Emit> {
+        match name {
+            "tokens"    => Some(Emit::Tokens),
+            "ast"       => Some(Emit::Ast),
+            "typed-ast" => Some(Emit::TypedAst),
+            "ir"        => Some(Emit::Ir),
+            "ir-opt"    => Some(Emit::IrOpt),
+            _ => None,
+        }
+    }
+}
+
+// how diagnostics are printed
+#[derive(PartialEq, Clone, Copy)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
 
 struct Args {
-    input: String,
-    uf: i8,
+    // one file, since each program's registers, labels and locals are
+    // numbered from zero and several couldn't share an output
+    input: Option<String>,
+    emit: Emit,
+    error_format: ErrorFormat,
+    output: Option<String>,
+    explain: Option<Code>,
+    // -W and -Wno- flags in the order given, the last one for a warning wins
+    lints: Vec<(Code, bool)>,
+    werror: bool,
+    uf: usize,
     lvn: bool,
+    help: bool,
+    version: bool,
 }
 
 impl Args {
-    fn new(args: &[String]) -> Result<Args, &'static str> {
-        if args.len() < 2 {
-            return Err("not enough arguments");
-        }
+    fn new(args: &[String]) -> Result<Args, String> {
         // init new args struct
         let mut new_args = Args {
-            input: String::new(),
+            input: None,
+            emit: Emit::Ir,
+            error_format: ErrorFormat::Human,
+            output: None,
+            explain: None,
+            lints: Vec::new(),
+            werror: false,
             uf: 1,
             lvn: false,
+            help: false,
+            version: false,
         };
-        for i in 1..args.len() {
-            if args[i].contains("-uf") {
-                new_args.uf = args[i + 1].to_string().parse::<i8>().unwrap_or_else(|_| {
-                    println!("Value passed to -uf should be an integer");
-                    std::process::exit(1);
-                });
-            } else if args[i].contains("-c") {
-                new_args.lvn = true;
-            } else {
-                new_args.input = args[i].clone();
+
+        let mut iter = args.iter().skip(1);
+        let mut only_inputs = false;
+        let mut emit = None;
+        while let Some(arg) = iter.next() {
+            if only_inputs || arg == "-" || !arg.starts_with('-') {
+                if let Some(input) = &new_args.input {
+                    return Err(format!("only one input file can be compiled at a time, got '{}' and '{}'", input, arg));
+                }
+                new_args.input = Some(arg.clone());
+                continue;
+            }
+            match arg.as_str() {
+                "--" => only_inputs = true,
+                "-h" | "--help" => new_args.help = true,
+                "--version" => new_args.version = true,
+                "-c" => new_args.lvn = true,
+                "-Werror" => new_args.werror = true,
+                "-o" => {
+                    let out = iter.next().ok_or("-o expects a file name")?;
+                    new_args.output = Some(out.clone());
+                },
+                "--explain" => {
+                    let code = iter.next().ok_or("--explain expects an error code")?;
+                    new_args.explain = Some(Code::from_name(code).ok_or(format!("unknown error code '{}'", code))?);
+                },
+                "-uf" => {
+                    let uf = iter.next().ok_or("-uf expects an unroll factor")?;
+                    new_args.uf = match uf.parse::<usize>() {
+                        Ok(uf) if uf >= 1 => uf,
+                        _ => return Err(format!("Value passed to -uf should be a positive integer, got '{}'", uf)),
+                    };
+                },
+                _ if arg.starts_with("--emit=") => {
+                    let stage = &arg["--emit=".len()..];
+                    emit = Some(Emit::from_name(stage).ok_or(format!("unknown stage '{}' passed to --emit", stage))?);
+                },
+                _ if arg.starts_with("--error-format=") => {
+                    new_args.error_format = match &arg["--error-format=".len()..] {
+                        "human" => ErrorFormat::Human,
+                        "json" => ErrorFormat::Json,
+                        format => return Err(format!("unknown format '{}' passed to --error-format", format)),
+                    };
+                },
+                _ if arg.starts_with("-W") => {
+                    let (name, on) = match arg.strip_prefix("-Wno-") {
+                        Some(name) => (name, false),
+                        None => (&arg["-W".len()..], true),
+                    };
+                    let code = Code::from_lint(name).ok_or(format!("unknown warning '{}'", name))?;
+                    new_args.lints.push((code, on));
+                },
+                _ => return Err(format!("unknown option '{}'", arg)),
             }
         }
-        if new_args.input.is_empty() {
-            return Err("No input file");
+
+        new_args.emit = match emit {
+            Some(emit) => emit,
+            None if new_args.lvn => Emit::IrOpt,
+            None => Emit::Ir,
+        };
+
+        if new_args.input.is_none() && !new_args.help && !new_args.version && new_args.explain.is_none() {
+            return Err("No input file".to_owned());
         }
         Ok(new_args)
     }
+
+    // drops the warnings that are turned off and, with -Werror, turns the
+    // rest into errors
+    fn apply_lints(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        diagnostics.into_iter()
+            .filter_map(|d| {
+                let Some((name, default)) = d.code.lint() else {
+                    return Some(d);
+                };
+                let flag = self.lints.iter().rev().find(|l| l.0 == d.code).map(|l| l.1);
+                if !flag.unwrap_or(default) {
+                    return None;
+                }
+                let mut d = match flag {
+                    Some(_) => d.with_note(format!("enabled by `-W{}`", name)),
+                    None => d.with_note(format!("`-W{}` is on by default", name)),
+                };
+                if self.werror {
+                    d = d.with_note("`-Werror` turns warnings into errors");
+                    d.severity = Severity::Error;
+                }
+                Some(d)
+            })
+            .collect()
+    }
+}
+
+fn read_input(input: &str) -> io::Result<String> {
+    if input == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(input)
+    }
+}
+
+// runs the pipeline up to the stage picked by --emit and returns its dump
+// with any warnings, or the errors that stopped it
+fn compile(source: &str, args: &Args) -> Result<(String, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.to_owned());
+    if args.emit == Emit::Tokens {
+        let mut out = String::new();
+        let mut errors = Vec::new();
+        while let Some(tok) = scanner.token() {
+            match tok {
+                Ok(tok) => {
+                    out.push_str(&format!("{}:{} {} {:?}", tok.span.line, tok.span.col, tok.kind, tok.lexeme));
+                    match tok.value {
+                        Some(Type::Int(i)) => out.push_str(&format!(" int {}", i)),
+                        Some(Type::Float(x)) => out.push_str(&format!(" float {:?}", x)),
+                        None => {},
+                    }
+                    out.push('\n');
+                },
+                Err(err) => errors.push(Diagnostic::from(err)),
+            }
+        }
+        return if errors.is_empty() { Ok((out, Vec::new())) } else { Err(errors) };
+    }
+
+    let mut parser = Parser::new(scanner, args.uf);
+    let ast_stage = match args.emit {
+        Emit::Ast => Some(AstStage::Parsed),
+        Emit::TypedAst => Some(AstStage::Typed),
+        _ => None,
+    };
+    if let Some(stage) = ast_stage {
+        parser.dump_ast(stage);
+    }
+
+    let mut program = parser.parse()?;
+    let warnings = parser.warnings();
+    if ast_stage.is_some() {
+        return Ok((parser.ast_dump().join("\n"), warnings));
+    }
+    if args.emit == Emit::IrOpt {
+        program.code = lvn::lvn(program.code);
+    }
+    Ok((program.to_string(), warnings))
+}
+
+// prints `diagnostics` in the format picked by --error-format, followed by
+// a summary in the human one
+fn emit_diagnostics(diagnostics: &[Diagnostic], path: &str, source: &str, format: ErrorFormat) {
+    if format == ErrorFormat::Json {
+        for d in diagnostics.iter() {
+            eprintln!("{}", d.to_json(path, source));
+        }
+        return;
+    }
+
+    for d in diagnostics.iter() {
+        eprintln!("{}", d.render(path, source));
+    }
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    if errors > 0 && warnings > 0 {
+        eprintln!("error: aborting due to {} previous error{}; {} warning{} emitted", errors, plural(errors), warnings, plural(warnings));
+    } else if errors > 0 {
+        eprintln!("error: aborting due to {} previous error{}", errors, plural(errors));
+    } else if warnings > 0 {
+        eprintln!("warning: {} warning{} emitted", warnings, plural(warnings));
+    }
+    if errors > 0 {
+        explain_hint(diagnostics);
+    }
+}
+
+// points at --explain for the codes of the errors in `diagnostics`
+fn explain_hint(diagnostics: &[Diagnostic]) {
+    let mut codes: Vec<Code> = diagnostics.iter().filter(|d| d.is_error()).map(|d| d.code).collect();
+    codes.sort_by_key(|c| c.as_str());
+    codes.dedup();
+    match codes.as_slice() {
+        [] => {},
+        [code] => eprintln!("For more information about this error, try `c-mini --explain {}`.", code),
+        [first, ..] => {
+            let names: Vec<&str> = codes.iter().map(|c| c.as_str()).collect();
+            eprintln!("Some errors have detailed explanations: {}.", names.join(", "));
+            eprintln!("For more information about an error, try `c-mini --explain {}`.", first);
+        },
+    }
 }
 
 fn main() {
     let env_args: Vec<String> = env::args().collect();
     let args = Args::new(&env_args).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        std::process::exit(1);
+        eprintln!("c-mini: {}", err);
+        eprintln!("Try 'c-mini --help' for more information.");
+        process::exit(EXIT_USAGE);
     });
-    // let f = File::open(&args.input);
-    // let f_contents = fs::read_to_string(&args.input).unwrap_or_else(|_| {
-    let f_contents = fs::read_to_string("test.c").unwrap_or_else(|_| {
-        println!("Error opening file");
-        std::process::exit(1);
+    if args.help {
+        print!("{}", USAGE);
+        return;
+    }
+    if args.version {
+        println!("c-mini {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if let Some(code) = args.explain {
+        print!("{}", code.explanation());
+        return;
+    }
+
+    let input = args.input.as_deref().unwrap_or("-");
+    let f_contents = read_input(input).unwrap_or_else(|err| {
+        eprintln!("c-mini: error: cannot read {}: {}", input, err);
+        process::exit(EXIT_COMPILE_ERROR);
     });
 
-    let mut scanner = Scanner::new(f_contents);
+    let path = if input == "-" { "<stdin>" } else { input };
+    let (output, diagnostics) = match compile(&f_contents, &args) {
+        Ok((out, warnings)) => (Some(out), args.apply_lints(warnings)),
+        Err(errors) => (None, args.apply_lints(errors)),
+    };
+    emit_diagnostics(&diagnostics, path, &f_contents, args.error_format);
+    let output = match output {
+        Some(out) if !diagnostics.iter().any(|d| d.is_error()) => out,
+        _ => process::exit(EXIT_COMPILE_ERROR),
+    };
 
-    loop {
-        let tok = &scanner.token();
-        if tok.is_none() {
-            break;
-        }
-        println!("{:?}", tok);
+    let written = match &args.output {
+        Some(path) => fs::write(path, output),
+        None => io::stdout().write_all(output.as_bytes()),
+    };
+    if let Err(err) = written {
+        eprintln!("c-mini: error: cannot write output: {}", err);
+        process::exit(EXIT_COMPILE_ERROR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        let args: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+        Args::new(&args)
+    }
+
+    #[test]
+    fn one_input() {
+        assert_eq!(args("c-mini a.c").map(|a| a.input), Ok(Some("a.c".to_owned())));
+        assert_eq!(args("c-mini -").map(|a| a.input), Ok(Some("-".to_owned())));
+        assert!(args("c-mini").is_err());
+    }
+
+    #[test]
+    fn more_than_one_input_is_rejected() {
+        assert_eq!(args("c-mini a.c b.c").err(),
+                   Some("only one input file can be compiled at a time, got 'a.c' and 'b.c'".to_owned()));
+        assert!(args("c-mini a.c -- b.c").is_err());
+    }
+
+    // an unused local, a narrowing assignment and a division by zero
+    const LINTED: &str = "void f(int &a, float &b) { int x; a = b; a = a / 0; }";
+
+    // the code and severity of each diagnostic left for LINTED by `line`
+    fn linted(line: &str) -> Vec<(Code, Severity)> {
+        let args = args(line).expect("valid arguments");
+        let (_, warnings) = compile(LINTED, &args).expect("program compiles");
+        args.apply_lints(warnings).iter().map(|d| (d.code, d.severity)).collect()
+    }
+
+    #[test]
+    fn default_lints() {
+        assert_eq!(linted("c-mini a.c"),
+                   [(Code::UnusedVariable, Severity::Warning), (Code::DivisionByZero, Severity::Warning)]);
+    }
+
+    #[test]
+    fn last_lint_flag_wins() {
+        assert_eq!(linted("c-mini -Wnarrowing -Wno-unused-variable a.c"),
+                   [(Code::ImplicitNarrowing, Severity::Warning), (Code::DivisionByZero, Severity::Warning)]);
+        assert_eq!(linted("c-mini -Wnarrowing -Wno-narrowing -Wno-div-by-zero -Wdiv-by-zero a.c"),
+                   [(Code::UnusedVariable, Severity::Warning), (Code::DivisionByZero, Severity::Warning)]);
+    }
+
+    #[test]
+    fn werror_promotes_only_enabled_warnings() {
+        let args = args("c-mini -Werror -Wno-unused-variable a.c").expect("valid arguments");
+        let (_, warnings) = compile(LINTED, &args).expect("program compiles");
+        let diagnostics = args.apply_lints(warnings);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].code, diagnostics[0].severity), (Code::DivisionByZero, Severity::Error));
+        assert!(diagnostics[0].notes.iter().any(|n| n.contains("-Werror")));
+        assert!(diagnostics[0].notes.iter().any(|n| n.contains("on by default")));
     }
-    println!("lineno: {:?}", scanner.lineno);
 }
@@ -1,7 +1,11 @@
 use std::collections::HashMap;
 
-use crate::scanner::{Scanner, Token};
+use crate::scanner::{LexError, Scanner, Span, Token, TokenKind};
 use crate::ast::*;
+use crate::codes::Code;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::ir::{Instr, Operand, Program};
+use crate::semantic::{self, Declaration, Reads};
 
 // struct VarInfo {
 //     val: Type,
@@ -9,7 +13,7 @@ use crate::ast::*;
 //     id_type: IDTypes,
 // }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum IDTypes {
     IO,
     Var,
@@ -46,6 +50,10 @@ impl NewNameGenerator {
         self.new_names.push(new_name.clone());
         new_name
     }
+
+    fn declare_variables(&self) -> Vec<Operand> {
+        self.new_names.iter().map(|n| Operand::Var(n.clone())).collect()
+    }
 }
 
 struct VRAllocator {
@@ -59,15 +67,15 @@ impl VRAllocator {
         }
     }
 
-    fn mk_new_vr(&mut self) -> String {
+    fn mk_new_vr(&mut self) -> Operand {
         self.counter += 1;
-        format!("vr{:?}", self.counter - 1)
+        Operand::VR(self.counter - 1)
     }
 
-    fn declare_variables(self) -> Vec<String> {
+    fn declare_variables(&self) -> Vec<Operand> {
         let mut ret = Vec::new();
         for i in 0..self.counter {
-            ret.push(format!("virtual_reg vr{:?}", i));
+            ret.push(Operand::VR(i));
         }
 
         ret
@@ -78,14 +86,17 @@ struct SymbolTableData {
     id_type: IDTypes,
     data_type: Type,
     new_name: String,
+    // where the id was declared
+    span: Span,
 }
 
 impl SymbolTableData {
-    pub fn new(&self, id_type: IDTypes, data_type: Type, new_name: String) -> Self {
+    pub fn new(id_type: IDTypes, data_type: Type, new_name: String, span: Span) -> Self {
         Self {
             id_type,
             data_type,
             new_name,
+            span,
         }
     }
 
@@ -98,7 +109,11 @@ impl SymbolTableData {
     }
 
     pub fn get_new_name(&self) -> String {
-        self.new_name
+        self.new_name.clone()
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
     }
 }
 
@@ -113,104 +128,357 @@ impl SymbolTable {
         }
     }
 
-    fn insert(mut self, lineno: usize, id: &str, info: SymbolTableData) {
-        // if variable is already in scope raise an error
-        if self.ht_stack[self.ht_stack.len() - 1].contains_key(id) {
-            panic!("Variable {} already declared at line {}", id, lineno);
-        }
+    // fails with the earlier declaration if `id` is already declared in the
+    // innermost scope
+    fn insert(&mut self, id: &str, info: SymbolTableData) -> Result<(), &SymbolTableData> {
         let n = self.ht_stack.len();
+        if self.ht_stack[n - 1].contains_key(id) {
+            return Err(&self.ht_stack[n - 1][id]);
+        }
         self.ht_stack[n - 1].insert(id.to_owned(), info);
+        Ok(())
     }
 
-    fn lookup(&self, id: Option<String>) -> Option<&SymbolTableData> {
-        if id.is_none() {
-            panic!("ID is none")
-        }
-        let id_val = id.unwrap();
+    // the innermost declaration of `id`
+    fn lookup(&self, id: &str) -> Option<&SymbolTableData> {
         for ht in self.ht_stack.iter().rev() {
-            match ht.get(&id_val) {
+            match ht.get(id) {
                 Some(v) => return Some(v),
                 None => continue
             };
         }
+        None
+    }
+
+    // entering a block, whose declarations shadow the ones around it
+    fn push_scope(&mut self) {
+        self.ht_stack.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        assert!(self.ht_stack.len() > 1, "popped the function's scope");
+        self.ht_stack.pop();
+    }
+}
+
+// an instruction with the source spans of the variables it reads, which
+// the semantic pass points its warnings at
+#[derive(Clone)]
+struct Lowered {
+    instr: Instr,
+    reads: Reads,
+}
+
+impl From<Instr> for Lowered {
+    fn from(instr: Instr) -> Self {
+        Self { instr, reads: Reads::new() }
+    }
+}
+
+// post-order walk over an expression whose vrs have been assigned, so every
+// child's code comes before its parent's and the lines are in execution order
+fn linearize_expr(node: &Node) -> Option<Vec<Lowered>> {
+    let mut program: Option<Vec<Lowered>> = None;
+    for child in node.children.iter() {
+        program = concat(program, linearize_expr(child));
+    }
+
+    let line = node.three_addr_code().map(|instr| Lowered { instr, reads: var_reads(&node.children) });
+    concat(program, line.map(|l| Vec::from([l])))
+}
+
+// the vrs and spans of the variables among `nodes`
+fn var_reads(nodes: &[Node]) -> Reads {
+    nodes.iter()
+        .filter(|n| n.node_type == NodeType::VarID)
+        .filter_map(|n| Some((n.vr.clone()?, n.span?)))
+        .collect()
+}
+
+// the number of iterations of `for (i = c0; i < c1; i = i + c2)` when i is
+// an int local and c0, c1 and c2 > 0 are int literals. `i <= c1` is
+// counted as `i < c1 + 1`, and `i++` or `i += c2` parse to the same update
+fn constant_trip_count(init: &Assignment, cond: &Node, update: &Assignment) -> Option<i64> {
+    if init.id_type != IDTypes::Var || update.new_name != init.new_name {
+        return None;
+    }
+    let loop_var = Operand::Var(init.new_name.clone());
+    let is_loop_var = |n: &Node| n.node_type == NodeType::VarID && n.vr.as_ref() == Some(&loop_var);
+    let int_literal = |n: &Node| match (&n.node_type, n.val_type) {
+        (NodeType::Num, Type::Int(i)) => Some(i as i64),
+        _ => None,
+    };
+
+    let c0 = int_literal(&init.ast)?;
+    if !matches!(cond.node_type, NodeType::Lt | NodeType::Le) || !is_loop_var(&cond.children[0]) {
         return None;
     }
+    let c1 = int_literal(&cond.children[1])? + (cond.node_type == NodeType::Le) as i64;
 
-    fn get_new_name(&mut self) {
+    let step = &update.ast;
+    if step.node_type != NodeType::Add {
+        return None;
+    }
+    let c2 = match (is_loop_var(&step.children[0]), is_loop_var(&step.children[1])) {
+        (true, _) => int_literal(&step.children[1])?,
+        (_, true) => int_literal(&step.children[0])?,
+        _ => return None,
+    };
+    if c2 <= 0 {
+        return None;
+    }
 
+    if c1 <= c0 {
+        Some(0)
+    } else {
+        Some((c1 - c0 + c2 - 1) / c2)
     }
 }
 
-fn linearize_expr(node: Node) -> Option<Vec<String>> {
-    todo!()
+fn concat<T>(v0: Option<Vec<T>>, v1: Option<Vec<T>>) -> Option<Vec<T>> {
+    match (v0, v1) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        },
+        (a, None) => a,
+        (None, b) => b,
+    }
 }
 
-struct Parser {
+// a parsed `id = expr` whose expression has been typed and converted to
+// the type of the id, but not lowered yet
+struct Assignment {
+    lineno: usize,
+    id_name: String,
+    id_type: IDTypes,
+    data_type: Type,
+    new_name: String,
+    ast: Node,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum AstStage {
+    // straight out of the parser
+    Parsed,
+    // after type inference and vr assignment
+    Typed,
+}
+
+pub struct Parser {
+    // unique names for locals, so a shadowing declaration gets its own
     nng: NewNameGenerator,
     symbol_table: SymbolTable,
     scanner: Scanner,
     vra: VRAllocator,
     nlg: NewLabelGenerator,
+    // unroll factor for for loops
     uf: usize,
     to_match: Option<Token>,
+    // span of the last token eaten
+    last_span: Option<Span>,
+    // where the last syntax error was reported
+    last_error: Option<usize>,
+    dump_stage: Option<AstStage>,
+    ast_dump: Vec<String>,
+    // errors that don't stop parsing, lexical ones included
+    diagnostics: Vec<Diagnostic>,
+    // what the semantic pass needs once the function is lowered
+    declarations: Vec<Declaration>,
+    // parentheses and unary operators open around the expression being parsed
+    nesting: usize,
 }
 
+// how deep an expression can nest, in operators and in parentheses and
+// unary operators. The parser and the walks over the tree recurse, so this
+// keeps them within the stack
+const MAX_EXPR_DEPTH: usize = 256;
+const MAX_NESTING: usize = 64;
+
+// the tokens a statement can start with
+const STATEMENT_START: [TokenKind; 8] = [
+    TokenKind::For, TokenKind::If, TokenKind::LBrace, TokenKind::Int,
+    TokenKind::Float, TokenKind::ID, TokenKind::Inc, TokenKind::Dec,
+];
+
+// a syntax error, which abandons the parse
+type PResult<T> = Result<T, Box<Diagnostic>>;
+
 impl Parser {
-    fn new(scanner: Scanner) -> Self {
+    pub fn new(scanner: Scanner, uf: usize) -> Self {
         Self {
             nng: NewNameGenerator::new(),
             symbol_table: SymbolTable::new(),
             scanner,
             vra: VRAllocator::new(),
             nlg: NewLabelGenerator::new(),
-            uf: 1,
+            uf,
             to_match: None,
+            last_span: None,
+            last_error: None,
+            dump_stage: None,
+            ast_dump: Vec::new(),
+            diagnostics: Vec::new(),
+            declarations: Vec::new(),
+            nesting: 0,
+        }
+    }
+
+    // error for a token none of `tokens` matched. A reserved word can't be
+    // what the parser expected, so it is reported as such instead
+    fn expected(&self, tokens: &[TokenKind]) -> Box<Diagnostic> {
+        let span = self.span();
+        let found = match &self.to_match {
+            Some(t) if t.kind == TokenKind::Reserved => {
+                return Box::new(Diagnostic::error(Code::UnsupportedKeyword, format!("unsupported keyword `{}`", t.lexeme), span)
+                    .with_label("C-Mini does not implement this yet"));
+            },
+            Some(t) => format!("`{}`", t.lexeme),
+            None => "end of input".to_owned(),
+        };
+        let expected = match tokens {
+            [] => "end of input".to_owned(),
+            [t] => t.to_string(),
+            _ => format!("one of {}", tokens.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(", ")),
+        };
+
+        // a missing `;` is pointed out where it belongs, after the last token,
+        // when the statement plainly ends there. Otherwise inserting one could
+        // split a line in the middle
+        let ends_statement = match &self.to_match {
+            Some(t) => t.kind == TokenKind::RBrace || self.last_span.is_some_and(|last| t.span.line > last.line),
+            None => true,
+        };
+        if let ([TokenKind::Semi], Some(last), true) = (tokens, self.last_span, ends_statement) {
+            let end = Span { start: last.end, end: last.end, col: last.col + last.end - last.start, ..last };
+            return Box::new(Diagnostic::error(Code::Syntax, format!("expected {}, found {}", expected, found), end)
+                .with_label(format!("expected {} here", expected))
+                .with_suggestion(end, ";", "insert `;`"));
         }
+
+        Box::new(Diagnostic::error(Code::Syntax, format!("expected {}, found {}", expected, found), span)
+            .with_label(format!("expected {}", expected)))
     }
 
-    fn parser_exception(&self, lineno: usize, to_match: Option<String>, tokens: &[&str]) {
-        panic!("Parser error on line {lineno}\nExpected one of: {:?}, found {:?}", tokens, to_match.unwrap_or("Null".to_owned()));
+    // span of the token being matched, or where the input ended
+    fn span(&self) -> Span {
+        match &self.to_match {
+            Some(t) => t.span,
+            None => self.scanner.position(),
+        }
     }
 
-    fn eat(&mut self, check: &'static str) {
-        self.eat_maybe(Some(check.to_owned()));
+    // line of the token being matched
+    fn lineno(&self) -> usize {
+        self.span().line
     }
 
-    fn eat_maybe(&mut self, check: Option<String>) {
-        let to_match = self.to_match.as_ref().and_then(|t| { Some(t.0.to_owned()) });
-        if to_match != check {
-            panic!("Expected {:?} but got {:?}", check, self.to_match);
+    // dumps the expression trees of the program as they reach `stage`
+    pub fn dump_ast(&mut self, stage: AstStage) {
+        self.dump_stage = Some(stage);
+    }
+
+    pub fn ast_dump(&self) -> &[String] {
+        &self.ast_dump
+    }
+
+    fn record_ast(&mut self, stage: AstStage, lineno: usize, what: &str, ast: &Node) {
+        if self.dump_stage == Some(stage) {
+            self.ast_dump.push(format!("line {}: {}\n{}", lineno, what, ast.tree(stage == AstStage::Typed)));
         }
-        self.to_match = self.scanner.token();
     }
 
-    fn combine(&self, mut v: Option<Vec<String>>, e: Option<String>) -> Option<Vec<String>> {
-        if e.is_none() { return v; }
+    // consumes the current token, which has to be a `check`, and returns it
+    fn eat(&mut self, check: TokenKind) -> PResult<Token> {
+        Ok(self.eat_maybe(Some(check))?.expect("matched a token at the end of input"))
+    }
 
-        match v {
-            Some(v1) => {
-                if e.is_some() {
-                    v1.push(e.unwrap());
-                }
-                Some(v1)
-            },
-            None => Some(Vec::from([e.unwrap()])),
+    fn eat_maybe(&mut self, check: Option<TokenKind>) -> PResult<Option<Token>> {
+        if self.get_token_id() != check {
+            return Err(self.expected(check.as_slice()));
+        }
+        Ok(self.advance())
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let next = self.next_token();
+        let eaten = std::mem::replace(&mut self.to_match, next);
+        self.last_span = eaten.as_ref().map(|t| t.span);
+        eaten
+    }
+
+    // records a syntax error unless one was already reported at the same
+    // spot, which happens when the end of the input leaves several blocks open
+    fn report(&mut self, err: Diagnostic) {
+        if self.last_error != Some(err.span.start) {
+            self.last_error = Some(err.span.start);
+            self.diagnostics.push(err);
         }
     }
 
-    fn get_token_id(&self) -> Option<String> {
-        self.to_match.as_ref().and_then(|t| { Some(t.0.to_owned()) })
+    // panic-mode recovery: reports `err` and skips the rest of the broken
+    // statement, up to a `;`, which is eaten, or up to the `}` or keyword
+    // after it. A block on the way is skipped whole and ends the statement
+    fn recover(&mut self, err: Diagnostic) {
+        self.report(err);
+        let mut depth = 0;
+        while let Some(kind) = self.get_token_id() {
+            match kind {
+                TokenKind::RBrace if depth == 0 => return,
+                TokenKind::If | TokenKind::For | TokenKind::Int | TokenKind::Float if depth == 0 => return,
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace | TokenKind::Semi => {
+                    depth -= (kind == TokenKind::RBrace) as usize;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                },
+                _ => {},
+            }
+            self.advance();
+        }
     }
 
-    fn check_tok_list(&self, id: &Option<String>, toks: &[&str]) -> bool {
-        if id.is_some() && toks.iter().any(|&i| i == id.unwrap()) {
-            return true;
+    // the next token, setting lexical errors aside so parsing can go on.
+    // A malformed number still stands for a number, which is taken to be 0
+    fn next_token(&mut self) -> Option<Token> {
+        loop {
+            let err = match self.scanner.token()? {
+                Ok(tok) => return Some(tok),
+                Err(err) => err,
+            };
+            let num = match &err {
+                LexError::InvalidNumber { lexeme, span } |
+                LexError::NumberOutOfRange { lexeme, span } => {
+                    Some(Token { kind: TokenKind::Num, lexeme: lexeme.clone(), span: *span, value: Some(Type::Int(0)) })
+                },
+                _ => None,
+            };
+            self.diagnostics.push(Diagnostic::from(err));
+            if num.is_some() {
+                return num;
+            }
         }
-        false
     }
 
-    fn check_tok(&self, id: &Option<String>, tok: &str) -> bool {
-        id.is_some() && id.unwrap() == tok
+    fn combine(&self, v: Option<Vec<Lowered>>, e: Option<Instr>) -> Option<Vec<Lowered>> {
+        concat(v, e.map(|e| Vec::from([e.into()])))
+    }
+
+    fn get_token_id(&self) -> Option<TokenKind> {
+        self.to_match.as_ref().map(|t| t.kind)
+    }
+
+    fn check_tok_list(&self, id: &Option<TokenKind>, toks: &[TokenKind]) -> bool {
+        match id {
+            Some(id) => toks.contains(id),
+            None => false,
+        }
+    }
+
+    fn check_tok(&self, id: &Option<TokenKind>, tok: TokenKind) -> bool {
+        *id == Some(tok)
     }
 
     fn assign_vrs(&mut self, node: &mut Node) {
@@ -218,214 +486,1106 @@ impl Parser {
             self.assign_vrs(&mut node.children[i]);
         }
 
-        let new_vr = self.vra.mk_new_vr();
-        node.set_vr(&new_vr);
+        // variables already live in their own register
+        if node.vr.is_none() {
+            let new_vr = self.vra.mk_new_vr();
+            node.set_vr(new_vr);
+        }
     }
 
-    fn parse(&mut self) {
-        self.to_match = self.scanner.token();
-        self.eat_maybe(None);
-        let p = self.parse_function();
+    // declares `id`, reporting a redeclaration in the same scope. Locals are
+    // renamed, IO arguments keep the name the caller knows them by
+    fn declare(&mut self, id: &Token, id_type: IDTypes, data_type: Type) -> String {
+        let new_name = match id_type {
+            IDTypes::Var => self.nng.mk_new_name(),
+            IDTypes::IO => id.lexeme.clone(),
+        };
+        let info = SymbolTableData::new(id_type, data_type, new_name.clone(), id.span);
+        let previous = match self.symbol_table.insert(&id.lexeme, info) {
+            Ok(()) => {
+                let operand = match id_type {
+                    IDTypes::Var => Operand::Var(new_name.clone()),
+                    IDTypes::IO => Operand::IO(new_name.clone()),
+                };
+                self.declarations.push(Declaration { name: id.lexeme.clone(), operand, span: id.span });
+                return new_name;
+            },
+            Err(previous) => (previous.get_id_type(), previous.get_span()),
+        };
+
+        let diag = match (previous.0, id_type) {
+            (IDTypes::IO, IDTypes::IO) => {
+                Diagnostic::error(Code::DuplicateParameter, format!("parameter `{}` is declared more than once", id.lexeme), id.span)
+                    .with_label("duplicate parameter")
+                    .with_secondary(previous.1, "first parameter with this name")
+            },
+            (IDTypes::IO, IDTypes::Var) => {
+                Diagnostic::error(Code::Redeclared, format!("`{}` is already declared in this scope", id.lexeme), id.span)
+                    .with_label("redeclared here")
+                    .with_secondary(previous.1, "declared as a parameter here")
+                    .with_note("parameters are in the same scope as the function body's outermost block")
+            },
+            _ => {
+                Diagnostic::error(Code::Redeclared, format!("`{}` is already declared in this scope", id.lexeme), id.span)
+                    .with_label("redeclared here")
+                    .with_secondary(previous.1, "first declared here")
+            },
+        };
+        self.diagnostics.push(diag);
 
-        p
+        new_name
     }
 
-    fn parse_function(&mut self) {
-        self.parse_function_header();
-        self.eat("LBRACE");
-        let p = self.parse_statement_list();
-        self.eat("RBRACE");
+    // the symbol table entry of `id`. An undeclared id is reported and then
+    // declared as an int, so each one is only reported once
+    fn resolve(&mut self, id: &Token) -> (IDTypes, Type, String) {
+        if let Some(d) = self.symbol_table.lookup(&id.lexeme) {
+            return (d.get_id_type(), d.get_data_type(), d.get_new_name());
+        }
+        self.diagnostics.push(
+            Diagnostic::error(Code::UndeclaredVariable, format!("use of undeclared variable `{}`", id.lexeme), id.span)
+                .with_label("not declared in this scope"));
+        let new_name = self.declare(id, IDTypes::Var, Type::Int(0));
 
-        p
+        (IDTypes::Var, Type::Int(0), new_name)
     }
 
-    fn parse_function_header(&mut self) {
-        self.eat("VOID");
-        // let func_name = self.to_match.and_then(|t| { Some(t.1.to_owned()) }).unwrap();
-        let _func_name: String;
-        if self.to_match.is_some() {
-            _func_name = self.to_match.as_ref().unwrap().1.to_owned();
-        } else {
-            panic!("Error parsing function name");
+    // fails with every error in the input, in source order. On success the
+    // warnings are left for `warnings`
+    pub fn parse(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        let p = self.eat_maybe(None)
+            .and_then(|_| self.parse_function())
+            .and_then(|p| self.eat_maybe(None).map(|_| p));
+
+        if let Err(err) = &p {
+            self.report(*err.clone());
         }
-        self.eat("ID");
-        self.eat("LPAR");
-        let _args = self.parse_args_list();
-        self.eat("RPAR");
+        if self.diagnostics.iter().any(|d| d.is_error()) {
+            let mut diagnostics = std::mem::take(&mut self.diagnostics);
+            diagnostics.sort_by_key(|d| d.span.start);
+            return Err(diagnostics);
+        }
+        let (code, reads): (Vec<Instr>, Vec<Reads>) = p.unwrap_or_default().unwrap_or_default()
+            .into_iter()
+            .map(|l| (l.instr, l.reads))
+            .unzip();
+        self.diagnostics.extend(semantic::check(&code, &reads, &self.declarations));
+
+        Ok(Program {
+            declarations: [self.nng.declare_variables(), self.vra.declare_variables()].concat(),
+            code,
+        })
     }
 
-    fn parse_args_list(&mut self) -> Option<Vec<String>> {
-        let mut token_id = self.get_token_id();
-        if self.check_tok(&token_id, "RPAR") {
-            return None;
+    // the warnings of a successful parse, in source order
+    pub fn warnings(&mut self) -> Vec<Diagnostic> {
+        let mut warnings = std::mem::take(&mut self.diagnostics);
+        warnings.sort_by_key(|d| d.span.start);
+        warnings
+    }
+
+    fn parse_function(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        self.parse_function_header()?;
+        self.eat(TokenKind::LBrace)?;
+        let p = self.parse_statement_list()?;
+        self.eat(TokenKind::RBrace)?;
+
+        Ok(p)
+    }
+
+    fn parse_function_header(&mut self) -> PResult<()> {
+        self.eat(TokenKind::Void)?;
+        let _func_name = self.eat(TokenKind::ID)?;
+        self.eat(TokenKind::LPar)?;
+        self.parse_args_list()?;
+        self.eat(TokenKind::RPar)?;
+
+        Ok(())
+    }
+
+    fn parse_args_list(&mut self) -> PResult<()> {
+        let token_id = self.get_token_id();
+        if self.check_tok(&token_id, TokenKind::RPar) {
+            return Ok(());
         }
-        let arg = self.parse_arg();
-        token_id = self.get_token_id();
-        if self.check_tok(&token_id, "RPAR") {
-            match arg {
-                Some(a) => return Some(Vec::from([a])),
-                None    => return None,
-            }
+        self.parse_arg()?;
+        let token_id = self.get_token_id();
+        if self.check_tok(&token_id, TokenKind::Comma) {
+            self.eat(TokenKind::Comma)?;
+            self.parse_args_list()?;
         }
-        let arg_list = self.parse_args_list();
-        return self.combine(arg_list, arg);
+
+        Ok(())
     }
 
-    fn parse_arg(&mut self) -> Option<String> {
+    fn parse_arg(&mut self) -> PResult<()> {
         let token_id = self.get_token_id();
         let data_type: Type;
-        let data_type_str: String;
-        if self.check_tok(&token_id, "FLOAT") {
-            self.eat("FLOAT");
+        if self.check_tok(&token_id, TokenKind::Float) {
+            self.eat(TokenKind::Float)?;
             data_type = Type::Float(0.0);
-            data_type_str = "float".to_string();
-        } else if self.check_tok(&token_id, "INT") {
-            self.eat("INT");
+        } else if self.check_tok(&token_id, TokenKind::Int) {
+            self.eat(TokenKind::Int)?;
             data_type = Type::Int(0);
-            data_type_str = "int".to_owned();
         } else {
-            self.parser_exception(111, self.get_token_id(), &["INT", "FLOAT"]);
+            return Err(self.expected(&[TokenKind::Int, TokenKind::Float]));
         }
 
-        self.eat("AMP");
-        let id_name = self.to_match.expect("Error: ID name not found").1;
-        self.eat("ID");
-        // self.symbol_table.insert(id_name, IDTypes.IO, data_type);
-        return None;
+        self.eat(TokenKind::Amp)?;
+        let id = self.eat(TokenKind::ID)?;
+        self.declare(&id, IDTypes::IO, data_type);
+
+        Ok(())
     }
 
-    fn parse_statement_list(&mut self) -> Option<Vec<String>> {
-        let token_id = self.get_token_id();
-        let p0: Option<String>;
-        let p1: Option<Vec<String>>;
-        if self.check_tok_list(&token_id, &["INT", "FLOAT", "ID", "IF", "LBRACE", "FOR"]) {
-            p0 = self.parse_statement();
-            p1 = self.parse_statement_list();
+    fn parse_statement_list(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        let mut program = Vec::new();
+        loop {
+            let token_id = self.get_token_id();
+            if self.check_tok(&token_id, TokenKind::RBrace) {
+                break;
+            }
+            let p = if self.check_tok_list(&token_id, &STATEMENT_START) {
+                self.parse_statement()
+            } else {
+                let mut expected = Vec::from(STATEMENT_START);
+                expected.push(TokenKind::RBrace);
+                Err(self.expected(&expected))
+            };
+            let p = p.unwrap_or_else(|err| {
+                self.recover(*err);
+                None
+            });
+            program.extend(p.unwrap_or_default());
+            // the `}` that should close the list is reported missing by the caller
+            if self.to_match.is_none() {
+                break;
+            }
         }
 
-        if self.check_tok(&token_id, "RBRACE") {
-            return None;
-        } else {
-            panic!("Unknown token found at the end of statement list");
-        }
+        Ok((!program.is_empty()).then_some(program))
     }
 
-    fn parse_statement(&self) -> Option<Vec<String>> {
+    fn parse_statement(&mut self) -> PResult<Option<Vec<Lowered>>> {
         let token_id = self.get_token_id();
-        let p: Option<Vec<String>>;
-        if self.check_tok_list(&token_id, &["INT", "FLOAT"]) {
-            self.parse_declaration_statement();
-            return None;
-        } else if self.check_tok(&token_id, "ID") {
-            p = self.parse_assignment_statement();
-            return p;
-        } else if self.check_tok(&token_id, "IF") {
-            p = self.parse_if_else_statement();
-            return p;
-        } else if self.check_tok(&token_id, "LBRACE") {
-            p = self.parse_block_statement();
-            return p;
-        } else if self.check_tok(&token_id, "FOR") {
-            p = self.parse_for_statement();
-            return p;
-        }
-
-        self.parser_exception(111, token_id, &["FOR", "IF", "LBRACE", "INT", "FLOAT", "ID"]);
-        None
+        if self.check_tok_list(&token_id, &[TokenKind::Int, TokenKind::Float]) {
+            self.parse_declaration_statement()?;
+            return Ok(None);
+        } else if self.check_tok_list(&token_id, &[TokenKind::ID, TokenKind::Inc, TokenKind::Dec]) {
+            return self.parse_assignment_statement();
+        } else if self.check_tok(&token_id, TokenKind::If) {
+            return self.parse_if_else_statement();
+        } else if self.check_tok(&token_id, TokenKind::LBrace) {
+            return self.parse_block_statement();
+        } else if self.check_tok(&token_id, TokenKind::For) {
+            return self.parse_for_statement();
+        }
+
+        Err(self.expected(&STATEMENT_START))
     }
 
-    fn parse_declaration_statement(&self) {
+    fn parse_declaration_statement(&mut self) -> PResult<()> {
         let token_id = self.get_token_id();
-        let id_name = self.to_match.as_ref().and_then(|t| { Some(t.1.to_owned()) });
-        if self.check_tok(&token_id, "INT") {
-            self.eat("INT");
-            let id_name = self.to_match.as_ref().and_then(|t| { Some(t.1.to_owned()) });
-        } else if self.check_tok(&token_id, "FLOAT") {
-            self.eat("FLOAT");
-            let id_name = self.to_match.as_ref().and_then(|t| { Some(t.1.to_owned()) });
+        let data_type: Type;
+        if self.check_tok(&token_id, TokenKind::Int) {
+            self.eat(TokenKind::Int)?;
+            data_type = Type::Int(0);
+        } else if self.check_tok(&token_id, TokenKind::Float) {
+            self.eat(TokenKind::Float)?;
+            data_type = Type::Float(0.0);
         } else {
-            self.parser_exception(111, token_id, &["INT", "FLOAT"]);
+            return Err(self.expected(&[TokenKind::Int, TokenKind::Float]));
         }
 
-        // self.symbol_table.insert(id_name, IDTypes.VAR, Type.Int);
-        self.eat("ID");
-        self.eat("SEMI");
-        return;
+        let id = self.eat(TokenKind::ID)?;
+        self.declare(&id, IDTypes::Var, data_type);
+        self.eat(TokenKind::Semi)?;
+
+        Ok(())
     }
 
-    fn parse_assignment_statement(&self) -> Option<Vec<String>> {
-        let p = self.parse_assignment_statement_base();
-        self.eat("SEMI");
-        p
+    fn parse_assignment_statement(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        let p = self.parse_assignment_statement_base()?;
+        self.eat(TokenKind::Semi)?;
+        Ok(p)
+    }
+
+    fn parse_assignment_statement_base(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        let assignment = self.parse_assignment()?;
+        Ok(self.lower_assignment(assignment))
     }
 
-    fn parse_assignment_statement_base(&self) -> Option<Vec<String>> {
-        let id_name = self.to_match.as_ref().and_then(|t| { Some(t.1.to_owned()) });
-        let id_data = self.symbol_table.lookup(id_name).unwrap_or_else(|| {
-            panic!("Id {:?} has not been created", id_name);
-        });
-        self.eat("ID");
-        self.eat("ASSIGN");
+    // parses `ID ASSIGN expr`, `ID op= expr`, `ID++` or `++ID` and their
+    // `--` forms, converting the expression to the type of the id.
+    // `x op= e` is read as `x = x op (e)` and `x++` as `x = x + 1`
+    fn parse_assignment(&mut self) -> PResult<Assignment> {
+        let lineno = self.lineno();
+        let prefix = self.get_token_id();
+        let prefix_op = prefix.and_then(step_op);
+        let prefix_span = self.span();
+        if prefix_op.is_some() {
+            self.eat_maybe(prefix)?;
+        }
 
-        let mut ast = self.parse_expr();
-        type_inference(ast);
+        let id = self.eat(TokenKind::ID)?;
+        let (id_type, data_type, new_name) = self.resolve(&id);
+        let target = self.id_node(&id, id_type, data_type, &new_name);
+        let one = match data_type {
+            Type::Int(_) => Node::new(NodeType::Num, Type::Int(1)),
+            Type::Float(_) => Node::new(NodeType::Num, Type::Float(1.0)),
+        };
 
-        if id_data.get_data_type() != ast.val_type {
-            match id_data.get_data_type() {
-                Type::Int(i) => ast = Node::grow_ast(NodeType::FloatToInt, id_data.get_data_type(), ast),
-                Type::Float(f) => ast = Node::grow_ast(NodeType::IntToFloat, id_data.get_data_type(), ast),
+        let token_id = self.get_token_id();
+        let op_span = self.span();
+        let mut ast = if let Some(op) = prefix_op {
+            self.binary_node(op, prefix_span, target, one)
+        } else if let Some(op) = token_id.and_then(step_op) {
+            self.eat_maybe(token_id)?;
+            self.binary_node(op, op_span, target, one)
+        } else if let Some(op) = token_id.and_then(compound_assign_op) {
+            self.eat_maybe(token_id)?;
+            let rhs = self.parse_expr()?;
+            self.binary_node(op, op_span, target, rhs)
+        } else if self.check_tok(&token_id, TokenKind::Assign) {
+            self.eat(TokenKind::Assign)?;
+            self.parse_expr()?
+        } else {
+            return Err(self.expected(&[TokenKind::Assign, TokenKind::PlusAssign, TokenKind::MinusAssign,
+                                       TokenKind::MulAssign, TokenKind::DivAssign, TokenKind::Inc, TokenKind::Dec]));
+        };
+        self.record_ast(AstStage::Parsed, lineno, &format!("assignment to {}", id.lexeme), &ast);
+        type_inference(&mut ast, &mut self.diagnostics);
+        lint_expr(&ast, &mut self.diagnostics);
+
+        if !data_type.same_type(&ast.val_type) {
+            match data_type {
+                Type::Int(_) => {
+                    let span = ast.span.unwrap_or(id.span);
+                    let mut diag = Diagnostic::new(Severity::Warning, Code::ImplicitNarrowing, format!("implicit conversion from float to int in assignment to `{}`", id.lexeme), span)
+                        .with_label("this float is truncated");
+                    if span != id.span {
+                        diag = diag.with_secondary(id.span, "this is an int");
+                    }
+                    self.diagnostics.push(diag);
+                    ast = Node::grow_ast(NodeType::FloatToInt, data_type, ast);
+                },
+                Type::Float(_) => ast = Node::grow_ast(NodeType::IntToFloat, data_type, ast),
             }
         }
 
+        Ok(Assignment {
+            lineno,
+            id_name: id.lexeme,
+            id_type,
+            data_type,
+            new_name,
+            ast,
+        })
+    }
+
+    fn lower_assignment(&mut self, assignment: Assignment) -> Option<Vec<Lowered>> {
+        let mut ast = assignment.ast;
         self.assign_vrs(&mut ast);
-        let program = linearize_expr(ast);
+        self.record_ast(AstStage::Typed, assignment.lineno, &format!("assignment to {}", assignment.id_name), &ast);
+        let program = linearize_expr(&ast);
+        let src = ast.vr.clone().expect("expression was not assigned a vr");
 
-        let assignment_program: String;
-        match id_data.get_id_type() {
+        let assignment_program = match assignment.id_type {
             IDTypes::Var => {
-                assignment_program = format!("{:?} = {:?};", id_data.get_new_name(), ast.vr);
+                Instr::Copy { dst: Operand::Var(assignment.new_name), src }
             },
             IDTypes::IO => {
-                let op = if id_data.get_data_type().is_int() {
-                    "vr2int".to_owned()
-                  } else {
-                    "vr2float".to_owned()
-                  };
-                assignment_program = format!("{:?} = {:?}({:?});", id_name, op, ast.vr);
+                let dst = Operand::IO(assignment.id_name);
+                if assignment.data_type.is_int() {
+                    Instr::VRToInt { dst, src }
+                } else {
+                    Instr::VRToFloat { dst, src }
+                }
             },
-        }
+        };
+        let store = Lowered { instr: assignment_program, reads: var_reads(std::slice::from_ref(&ast)) };
 
-        self.combine(program, Some(assignment_program))
+        concat(program, Some(Vec::from([store])))
     }
 
-    fn parse_if_else_statement(&self) -> Option<Vec<String>> {
-        self.eat("IF");
-        self.eat("LPAR");
+    // parses the condition of an if or a for loop, returning its typed tree
+    fn parse_condition(&mut self, what: &str) -> PResult<Node> {
+        let lineno = self.lineno();
+        let mut ast = self.parse_expr()?;
+        self.record_ast(AstStage::Parsed, lineno, what, &ast);
+        type_inference(&mut ast, &mut self.diagnostics);
+        lint_expr(&ast, &mut self.diagnostics);
 
-        let ast = self.parse_expr();
-        type_inference(ast);
+        Ok(ast)
+    }
+
+    // code computing the condition and jumping to `label` when it is zero
+    fn lower_condition(&mut self, mut ast: Node, lineno: usize, what: &str, label: &str) -> Vec<Lowered> {
         self.assign_vrs(&mut ast);
-        let p0 = linearize_expr(ast);
+        self.record_ast(AstStage::Typed, lineno, what, &ast);
+        let program = linearize_expr(&ast);
+        let vr = ast.vr.clone().expect("condition was not assigned a vr");
 
+        // compared against zero of the same type
         let zero = self.vra.mk_new_vr();
+        let i0 = match ast.val_type {
+            Type::Int(_) => Instr::IntToVR { dst: zero.clone(), src: Operand::Imm(Type::Int(0)) },
+            Type::Float(_) => Instr::FloatToVR { dst: zero.clone(), src: Operand::Imm(Type::Float(0.0)) },
+        };
+        let i1 = Lowered {
+            instr: Instr::Beq { lhs: vr, rhs: zero, label: label.to_owned() },
+            reads: var_reads(std::slice::from_ref(&ast)),
+        };
+
+        concat(program, Some(Vec::from([i0.into(), i1]))).unwrap_or_default()
+    }
+
+    fn parse_if_else_statement(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        self.eat(TokenKind::If)?;
+        self.eat(TokenKind::LPar)?;
+
+        let lineno = self.lineno();
+        let cond = self.parse_condition("if condition")?;
         let else_label = self.nlg.mk_new_label();
         let end_label = self.nlg.mk_new_label();
-        let i0 = format!("{:?} = int2vr(0);")
+        let p0 = self.lower_condition(cond, lineno, "if condition", &else_label);
+        self.eat(TokenKind::RPar)?;
+
+        let p1 = self.parse_scoped_statement()?;
+        let i1 = Instr::Branch(end_label.clone());
+        let i2 = Instr::Label(else_label);
+
+        let token_id = self.get_token_id();
+        let p2 = if self.check_tok(&token_id, TokenKind::Else) {
+            self.eat(TokenKind::Else)?;
+            self.parse_scoped_statement()?
+        } else {
+            None
+        };
+        let i3 = Instr::Label(end_label);
+
+        let mut program = concat(Some(p0), p1);
+        program = concat(program, Some(Vec::from([i1.into(), i2.into()])));
+        program = concat(program, p2);
+        Ok(self.combine(program, Some(i3)))
+    }
+
+    fn parse_block_statement(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        self.eat(TokenKind::LBrace)?;
+        self.symbol_table.push_scope();
+        let p = self.parse_statement_list();
+        self.symbol_table.pop_scope();
+        self.eat(TokenKind::RBrace)?;
+
+        p
+    }
+
+    // the body of an if, else or for, which gets a scope of its own even
+    // when it isn't a block
+    fn parse_scoped_statement(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        self.symbol_table.push_scope();
+        let p = self.parse_statement();
+        self.symbol_table.pop_scope();
+
+        p
+    }
+
+    // the body is unrolled `uf` times, re-checking the condition before each
+    // copy so any trip count stays correct:
+    //
+    //     init
+    //     start:
+    //     (cond; beq(cond, 0, end); body; update) * uf
+    //     branch(start)
+    //     end:
+    //
+    // when the trip count is a constant multiple of uf only the first check
+    // of each iteration is kept
+    fn parse_for_statement(&mut self) -> PResult<Option<Vec<Lowered>>> {
+        self.eat(TokenKind::For)?;
+        self.eat(TokenKind::LPar)?;
+        let init = self.parse_assignment()?;
+        self.eat(TokenKind::Semi)?;
+
+        let cond_lineno = self.lineno();
+        let cond = self.parse_condition("for condition")?;
+        self.eat(TokenKind::Semi)?;
+
+        let update = self.parse_assignment()?;
+        self.eat(TokenKind::RPar)?;
+
+        let trip_count = constant_trip_count(&init, &cond, &update);
+        let loop_var = Operand::Var(init.new_name.clone());
+        let p0 = self.lower_assignment(init);
+
+        let start_label = self.nlg.mk_new_label();
+        let end_label = self.nlg.mk_new_label();
+        let i0 = Instr::Label(start_label.clone());
+
+        let check = self.lower_condition(cond, cond_lineno, "for condition", &end_label);
+
+        let p2 = self.lower_assignment(update).unwrap_or_default();
+        let body = self.parse_scoped_statement()?.unwrap_or_default();
+
+        // the fast path relies on the body leaving the loop variable alone
+        let writes_loop_var = body.iter().any(|l| matches!(&l.instr, Instr::Copy { dst, .. } if *dst == loop_var));
+        let skip_checks = match trip_count {
+            Some(n) => !writes_loop_var && n % self.uf as i64 == 0,
+            None => false,
+        };
+
+        let mut program = self.combine(p0, Some(i0));
+        for copy in 0..self.uf {
+            if copy == 0 || !skip_checks {
+                program = concat(program, Some(check.clone()));
+            }
+            // labels inside the body have to be unique to each copy
+            let body_copy = if copy == 0 { body.clone() } else { self.relabel(&body) };
+            program = concat(program, Some(body_copy));
+            program = concat(program, Some(p2.clone()));
+        }
+
+        let i2 = Instr::Branch(start_label);
+        let i3 = Instr::Label(end_label);
+        Ok(concat(program, Some(Vec::from([i2.into(), i3.into()]))))
+    }
+
+    // copy of `code` with every label it defines replaced by a fresh one
+    fn relabel(&mut self, code: &[Lowered]) -> Vec<Lowered> {
+        let mut labels = HashMap::new();
+        for line in code.iter() {
+            if let Instr::Label(label) = &line.instr {
+                labels.insert(label.clone(), self.nlg.mk_new_label());
+            }
+        }
+        let rename = |label: &String| labels.get(label).unwrap_or(label).clone();
+
+        code.iter().map(|line| {
+            let instr = match &line.instr {
+                Instr::Label(label) => Instr::Label(rename(label)),
+                Instr::Branch(label) => Instr::Branch(rename(label)),
+                Instr::Beq { lhs, rhs, label } => Instr::Beq { lhs: lhs.clone(), rhs: rhs.clone(), label: rename(label) },
+                Instr::Bne { lhs, rhs, label } => Instr::Bne { lhs: lhs.clone(), rhs: rhs.clone(), label: rename(label) },
+                instr => instr.clone(),
+            };
+            Lowered { instr, reads: line.reads.clone() }
+        }).collect()
     }
 
-    fn parse_block_statement(&self) -> Option<Vec<String>> {
-        todo!()
+    // expr := binary [(ASSIGN | op=) expr]. Assignments are statements, one
+    // inside an expression is reported and skipped
+    fn parse_expr(&mut self) -> PResult<Node> {
+        let node = self.parse_binary_expr(0)?;
+        let mut token_id = self.get_token_id();
+        while token_id.is_some_and(|t| t == TokenKind::Assign || compound_assign_op(t).is_some()) {
+            let op = self.eat_maybe(token_id)?.expect("operator token");
+            self.report_statement_op(&op);
+            self.parse_binary_expr(0)?;
+            token_id = self.get_token_id();
+        }
+
+        Ok(node)
+    }
+
+    // runs `parse` one level deeper into parentheses or unary operators
+    fn nested(&mut self, parse: fn(&mut Self) -> PResult<Node>) -> PResult<Node> {
+        if self.nesting == MAX_NESTING {
+            return Err(too_deep(self.span(), format!("this goes past {} parentheses and unary operators", MAX_NESTING)));
+        }
+        self.nesting += 1;
+        let node = parse(self);
+        self.nesting -= 1;
+        node
     }
 
-    fn parse_for_statement(&self) -> Option<Vec<String>> {
-        todo!()
+    // `=`, `op=`, `++` or `--` where an expression is being parsed. C-Mini
+    // expressions have no side effects, so these only make statements
+    fn report_statement_op(&mut self, op: &Token) {
+        let mut diag = Diagnostic::error(Code::AssignmentInExpression, format!("`{}` can't be used inside an expression", op.lexeme), op.span);
+        diag = match op.kind {
+            TokenKind::Inc | TokenKind::Dec => diag.with_label("increments and decrements are statements in C-Mini"),
+            TokenKind::Assign => diag.with_label("assignments are statements in C-Mini")
+                .with_note("to compare two values use `==`"),
+            _ => diag.with_label("assignments are statements in C-Mini"),
+        };
+        self.diagnostics.push(diag.with_note("move it into a statement of its own"));
     }
 
-    fn parse_expr(&self) -> Node {
-        todo!()
+    // precedence climbing: keeps folding operators that bind at least as
+    // tightly as `min_prec` into the left operand, which makes every level
+    // left associative
+    fn parse_binary_expr(&mut self, min_prec: u8) -> PResult<Node> {
+        let mut node = self.parse_unary_expr()?;
+        let mut depth = tree_depth(&node);
+        loop {
+            let token_id = self.get_token_id();
+            let (node_type, prec) = match token_id.and_then(binary_op) {
+                Some((node_type, prec)) if prec >= min_prec => (node_type, prec),
+                _ => return Ok(node),
+            };
+            let op = self.eat_maybe(token_id)?.expect("operator token");
+            let rhs = self.parse_binary_expr(prec + 1)?;
+            depth = depth.max(tree_depth(&rhs)) + 1;
+            if depth > MAX_EXPR_DEPTH {
+                return Err(too_deep(op.span, format!("this goes past {} levels of operators", MAX_EXPR_DEPTH)));
+            }
+            node = self.binary_node(node_type, op.span, node, rhs);
+        }
+    }
+
+    // unary := (MINUS | NOT | TILDE) unary | unit
+    fn parse_unary_expr(&mut self) -> PResult<Node> {
+        let token_id = self.get_token_id();
+        if token_id.and_then(step_op).is_some() {
+            let op = self.eat_maybe(token_id)?.expect("operator token");
+            self.report_statement_op(&op);
+            return self.nested(Self::parse_unary_expr);
+        }
+        if !self.check_tok_list(&token_id, &[TokenKind::Minus, TokenKind::Not, TokenKind::Tilde]) {
+            return self.parse_unit();
+        }
+        let op = self.eat_maybe(token_id)?.expect("operator token");
+        let operand = self.nested(Self::parse_unary_expr)?;
+
+        // fold literals, otherwise -x becomes 0 - x, !x becomes x == 0 and
+        // ~x becomes x ^ -1 in a BitNot node
+        let mut node = match (token_id, &operand.node_type, operand.val_type) {
+            (Some(TokenKind::Minus), NodeType::Num, Type::Int(i)) => Node::new(NodeType::Num, Type::Int(i.wrapping_neg())),
+            (Some(TokenKind::Minus), NodeType::Num, Type::Float(f)) => Node::new(NodeType::Num, Type::Float(-f)),
+            (Some(TokenKind::Minus), _, t) => self.binary_node(NodeType::Sub, op.span, zero(t), operand),
+            (Some(TokenKind::Not), NodeType::Num, t) => Node::new(NodeType::Num, Type::Int((t == zero(t).val_type) as i32)),
+            (Some(TokenKind::Not), _, t) => self.binary_node(NodeType::Eq, op.span, operand, zero(t)),
+            (_, NodeType::Num, Type::Int(i)) => Node::new(NodeType::Num, Type::Int(!i)),
+            (_, _, _) => self.binary_node(NodeType::BitNot, op.span, operand, Node::new(NodeType::Num, Type::Int(-1))),
+        };
+        if node.span.is_none() {
+            node.set_span(op.span);
+        }
+
+        Ok(node)
+    }
+
+    // unit := NUM | ID | LPAR expr RPAR
+    fn parse_unit(&mut self) -> PResult<Node> {
+        let token_id = self.get_token_id();
+        if self.check_tok(&token_id, TokenKind::Num) {
+            let num = self.eat(TokenKind::Num)?;
+            // the scanner has already worked out the literal's type and value
+            let mut node = Node::new(NodeType::Num, num.value.expect("NUM token has no value"));
+            node.set_span(num.span);
+            return Ok(node);
+        } else if self.check_tok(&token_id, TokenKind::ID) {
+            let id = self.eat(TokenKind::ID)?;
+            let (id_type, data_type, new_name) = self.resolve(&id);
+            let token_id = self.get_token_id();
+            if token_id.and_then(step_op).is_some() {
+                let op = self.eat_maybe(token_id)?.expect("operator token");
+                self.report_statement_op(&op);
+            }
+            return Ok(self.id_node(&id, id_type, data_type, &new_name));
+        } else if self.check_tok(&token_id, TokenKind::LPar) {
+            self.eat(TokenKind::LPar)?;
+            let node = self.nested(Self::parse_expr)?;
+            self.eat(TokenKind::RPar)?;
+            return Ok(node);
+        }
+
+        Err(self.expected(&[TokenKind::Num, TokenKind::ID, TokenKind::LPar]))
+    }
+
+    // leaf reading the id `id`
+    fn id_node(&self, id: &Token, id_type: IDTypes, data_type: Type, new_name: &str) -> Node {
+        let mut node = match id_type {
+            IDTypes::Var => {
+                let mut node = Node::new_id(NodeType::VarID, data_type, &id.lexeme);
+                node.set_vr(Operand::Var(new_name.to_owned()));
+                node
+            },
+            IDTypes::IO => Node::new_id(NodeType::IOID, data_type, &id.lexeme),
+        };
+        node.set_span(id.span);
+        node
+    }
+
+    // `span` is the operator's
+    fn binary_node(&self, node_type: NodeType, span: Span, lhs: Node, rhs: Node) -> Node {
+        let mut node = Node::new(node_type, Type::Int(0));
+        node.children = Vec::from([lhs, rhs]);
+        node.set_span(span);
+        node
     }
 }
 
-fn type_inference(ast: Node) {
-    todo!()
+// binary operators with their C precedence, higher binds tighter
+fn binary_op(token_id: TokenKind) -> Option<(NodeType, u8)> {
+    match token_id {
+        TokenKind::Or    => Some((NodeType::Or, 1)),
+        TokenKind::And   => Some((NodeType::And, 2)),
+        TokenKind::Pipe  => Some((NodeType::BitOr, 3)),
+        TokenKind::Caret => Some((NodeType::BitXor, 4)),
+        TokenKind::Amp   => Some((NodeType::BitAnd, 5)),
+        TokenKind::Eq    => Some((NodeType::Eq, 6)),
+        TokenKind::Ne    => Some((NodeType::Ne, 6)),
+        TokenKind::Lt    => Some((NodeType::Lt, 7)),
+        TokenKind::Gt    => Some((NodeType::Gt, 7)),
+        TokenKind::Le    => Some((NodeType::Le, 7)),
+        TokenKind::Ge    => Some((NodeType::Ge, 7)),
+        TokenKind::Shl   => Some((NodeType::Shl, 8)),
+        TokenKind::Shr   => Some((NodeType::Shr, 8)),
+        TokenKind::Plus  => Some((NodeType::Add, 9)),
+        TokenKind::Minus => Some((NodeType::Sub, 9)),
+        TokenKind::Mul   => Some((NodeType::Mult, 10)),
+        TokenKind::Div   => Some((NodeType::Div, 10)),
+        TokenKind::Mod   => Some((NodeType::Mod, 10)),
+        _ => None,
+    }
+}
+
+// the operation `id op= expr` applies before storing into id
+fn compound_assign_op(token_id: TokenKind) -> Option<NodeType> {
+    match token_id {
+        TokenKind::PlusAssign  => Some(NodeType::Add),
+        TokenKind::MinusAssign => Some(NodeType::Sub),
+        TokenKind::MulAssign   => Some(NodeType::Mult),
+        TokenKind::DivAssign   => Some(NodeType::Div),
+        _ => None,
+    }
+}
+
+// `++` and `--`, as the operation adding or taking one
+fn step_op(token_id: TokenKind) -> Option<NodeType> {
+    match token_id {
+        TokenKind::Inc => Some(NodeType::Add),
+        TokenKind::Dec => Some(NodeType::Sub),
+        _ => None,
+    }
+}
+
+// a literal zero of the same type as `t`
+fn zero(t: Type) -> Node {
+    match t {
+        Type::Int(_) => Node::new(NodeType::Num, Type::Int(0)),
+        Type::Float(_) => Node::new(NodeType::Num, Type::Float(0.0)),
+    }
+}
+
+// levels in the tree under `ast`, counted without recursing
+fn tree_depth(ast: &Node) -> usize {
+    let mut depth = 0;
+    let mut stack = Vec::from([(ast, 1)]);
+    while let Some((node, d)) = stack.pop() {
+        depth = depth.max(d);
+        stack.extend(node.children.iter().map(|c| (c, d + 1)));
+    }
+    depth
+}
+
+fn too_deep(span: Span, label: String) -> Box<Diagnostic> {
+    Box::new(Diagnostic::error(Code::ExpressionTooDeep, "expression is nested too deeply", span)
+        .with_label(label)
+        .with_note("split the expression across statements, keeping the parts in locals"))
+}
+
+// sets val_type bottom-up. Operands of a binary operation are brought to
+// their common type first, so an int side of a mixed operation gets wrapped
+// in an IntToFloat node. Returns the type of `ast`, type errors go to
+// `diagnostics`
+fn type_inference(ast: &mut Node, diagnostics: &mut Vec<Diagnostic>) -> Type {
+    if ast.children.is_empty() {
+        return ast.val_type;
+    }
+
+    let types: Vec<Type> = ast.children.iter_mut().map(|c| type_inference(c, diagnostics)).collect();
+    ast.val_type = match ast.node_type {
+        NodeType::IntToFloat => Type::Float(0.0),
+        NodeType::FloatToInt => Type::Int(0),
+        // a float side is turned into an int truth value with `!= 0.0`
+        _ if ast.node_type.is_logical() => {
+            for (child, t) in ast.children.iter_mut().zip(types) {
+                if t.is_float() {
+                    let old = std::mem::replace(child, Node::new(NodeType::Ne, Type::Int(0)));
+                    child.children = Vec::from([old, zero(t)]);
+                }
+            }
+            Type::Int(0)
+        },
+        _ => {
+            let common = types.iter().skip(1).fold(types[0], |acc, t| acc.usual_arithmetic_conversion(t));
+            if ast.node_type.is_int_only() && common.is_float() {
+                let span = ast.span.expect("operator node without a span");
+                let mut diag = Diagnostic::error(Code::IntOnlyOperator, format!("invalid operands to `{}`", ast.node_type.symbol()), span)
+                    .with_label("only defined on ints");
+                for (child, t) in ast.children.iter().zip(types.iter()) {
+                    if let (Some(span), true) = (child.span, t.is_float()) {
+                        diag = diag.with_secondary(span, "this is a float");
+                    }
+                }
+                diagnostics.push(diag);
+            }
+            for (child, t) in ast.children.iter_mut().zip(types) {
+                if !t.same_type(&common) {
+                    let old = std::mem::replace(child, Node::new(NodeType::Leaf, common));
+                    *child = Node::grow_ast(NodeType::IntToFloat, common, old);
+                }
+            }
+
+            // comparisons are computed in the common type but yield an int
+            if ast.node_type.is_comparison() {
+                Type::Int(0)
+            } else {
+                common
+            }
+        }
+    };
+
+    ast.val_type
+}
+
+// warnings about a typed expression: float equality, int division by a
+// literal zero and overflow in constant expressions. Returns the value of
+// an int expression made only of literals
+fn lint_expr(ast: &Node, diagnostics: &mut Vec<Diagnostic>) -> Option<i32> {
+    let values: Vec<Option<i32>> = ast.children.iter().map(|c| lint_expr(c, diagnostics)).collect();
+    if ast.node_type == NodeType::Num {
+        return match ast.val_type {
+            Type::Int(i) => Some(i),
+            Type::Float(_) => None,
+        };
+    }
+    // nodes the compiler adds have no span and aren't linted
+    let (Some(span), [lhs, rhs]) = (ast.span, ast.children.as_slice()) else {
+        return None;
+    };
+    let symbol = ast.node_type.symbol();
+
+    // `!x` compares a float against a zero the parser made up
+    let made_up_zero = ast.children.iter().any(|c| c.node_type == NodeType::Num && c.span.is_none());
+    if matches!(ast.node_type, NodeType::Eq | NodeType::Ne) && lhs.val_type.is_float() && !made_up_zero {
+        diagnostics.push(Diagnostic::new(Severity::Warning, Code::FloatEquality, format!("comparing floats with `{}`", symbol), span)
+            .with_label("rounding can make equal values compare unequal"));
+    }
+    if matches!(ast.node_type, NodeType::Div | NodeType::Mod) && rhs.node_type == NodeType::Num && rhs.val_type == Type::Int(0) {
+        diagnostics.push(Diagnostic::new(Severity::Warning, Code::DivisionByZero, "int division by zero", span)
+            .with_label(format!("`{}` by a literal zero", symbol)));
+        return None;
+    }
+
+    let (Some(a), Some(b)) = (values[0], values[1]) else {
+        return None;
+    };
+    let (a, b) = (a as i64, b as i64);
+    let value = match ast.node_type {
+        NodeType::Add => a + b,
+        NodeType::Sub => a - b,
+        NodeType::Mult => a * b,
+        NodeType::Div | NodeType::Mod if b == 0 => return None,
+        NodeType::Div => a / b,
+        NodeType::Mod => a % b,
+        NodeType::Shl | NodeType::Shr if !(0..32).contains(&b) => {
+            diagnostics.push(Diagnostic::new(Severity::Warning, Code::ConstantOverflow, format!("shift count {} is out of range", b), span)
+                .with_label("ints are 32 bits wide, so the count has to be between 0 and 31"));
+            return None;
+        },
+        NodeType::Shl => a << b,
+        NodeType::Shr => a >> b,
+        NodeType::BitAnd => a & b,
+        NodeType::BitOr => a | b,
+        NodeType::BitXor |
+        NodeType::BitNot => a ^ b,
+        NodeType::Eq => (a == b) as i64,
+        NodeType::Ne => (a != b) as i64,
+        NodeType::Lt => (a < b) as i64,
+        NodeType::Gt => (a > b) as i64,
+        NodeType::Le => (a <= b) as i64,
+        NodeType::Ge => (a >= b) as i64,
+        NodeType::And => (a != 0 && b != 0) as i64,
+        NodeType::Or => (a != 0 || b != 0) as i64,
+        _ => return None,
+    };
+
+    let wrapped = value as i32;
+    if wrapped as i64 != value {
+        diagnostics.push(Diagnostic::new(Severity::Warning, Code::ConstantOverflow, "integer overflow in constant expression", span)
+            .with_label(format!("`{} {} {}` does not fit in an int", a, symbol, b))
+            .with_note(format!("the result wraps to {}", wrapped)));
+    }
+
+    Some(wrapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Node {
+        let mut node = Node::new_id(NodeType::VarID, Type::Int(0), name);
+        node.set_vr(Operand::Var(name.to_owned()));
+        node
+    }
+
+    fn int(i: i32) -> Node {
+        Node::new(NodeType::Num, Type::Int(i))
+    }
+
+    fn binary(node_type: NodeType, lhs: Node, rhs: Node) -> Node {
+        let mut node = Node::new(node_type, Type::Int(0));
+        node.children = Vec::from([lhs, rhs]);
+        node
+    }
+
+    fn assign(name: &str, ast: Node) -> Assignment {
+        Assignment {
+            lineno: 1,
+            id_name: name.to_owned(),
+            id_type: IDTypes::Var,
+            data_type: Type::Int(0),
+            new_name: name.to_owned(),
+            ast,
+        }
+    }
+
+    // trip count of `for (i = c0; i <op> c1; i = i + c2)`
+    fn trip_count(c0: i32, cmp: NodeType, c1: i32, c2: i32) -> Option<i64> {
+        let init = assign("i", int(c0));
+        let cond = binary(cmp, var("i"), int(c1));
+        let update = assign("i", binary(NodeType::Add, var("i"), int(c2)));
+        constant_trip_count(&init, &cond, &update)
+    }
+
+    #[test]
+    fn constant_trip_counts() {
+        assert_eq!(trip_count(0, NodeType::Lt, 10, 1), Some(10));
+        assert_eq!(trip_count(0, NodeType::Le, 10, 1), Some(11));
+        assert_eq!(trip_count(0, NodeType::Lt, 10, 3), Some(4));
+        assert_eq!(trip_count(2, NodeType::Lt, 10, 2), Some(4));
+        assert_eq!(trip_count(10, NodeType::Lt, 10, 1), Some(0));
+        assert_eq!(trip_count(20, NodeType::Lt, 10, 1), Some(0));
+    }
+
+    #[test]
+    fn no_trip_count_for_other_loops() {
+        assert_eq!(trip_count(0, NodeType::Gt, 10, 1), None);
+        assert_eq!(trip_count(0, NodeType::Lt, 10, 0), None);
+        assert_eq!(trip_count(0, NodeType::Lt, 10, -1), None);
+
+        // bound that isn't a literal
+        let init = assign("i", int(0));
+        let cond = binary(NodeType::Lt, var("i"), var("n"));
+        let update = assign("i", binary(NodeType::Add, var("i"), int(1)));
+        assert_eq!(constant_trip_count(&init, &cond, &update), None);
+
+        // update of another variable
+        let cond = binary(NodeType::Lt, var("i"), int(10));
+        let update = assign("j", binary(NodeType::Add, var("j"), int(1)));
+        assert_eq!(constant_trip_count(&init, &cond, &update), None);
+    }
+
+    fn compile(source: &str, uf: usize) -> Vec<Instr> {
+        let mut parser = Parser::new(Scanner::new(source.to_owned()), uf);
+        parser.parse().expect("test program compiles").code
+    }
+
+    // the loop's condition checks and copies of its body
+    fn checks_and_bodies(code: &[Instr]) -> (usize, usize) {
+        let checks = code.iter().filter(|i| matches!(i, Instr::Beq { .. })).count();
+        let bodies = code.iter().filter(|i| matches!(i, Instr::VRToInt { .. })).count();
+        (checks, bodies)
+    }
+
+    fn counting_loop(bound: i32) -> String {
+        format!("void f(int &a) {{ int i; for (i = 0; i < {}; i++) {{ a = a + i; }} }}", bound)
+    }
+
+    #[test]
+    fn unroll_without_unrolling() {
+        assert_eq!(checks_and_bodies(&compile(&counting_loop(8), 1)), (1, 1));
+    }
+
+    #[test]
+    fn unroll_divisible_trip_count_keeps_one_check() {
+        assert_eq!(checks_and_bodies(&compile(&counting_loop(8), 4)), (1, 4));
+    }
+
+    #[test]
+    fn unroll_non_divisible_trip_count_checks_every_copy() {
+        assert_eq!(checks_and_bodies(&compile(&counting_loop(10), 4)), (4, 4));
+    }
+
+    #[test]
+    fn unroll_unknown_trip_count_checks_every_copy() {
+        let source = "void f(int &a) { int i; for (i = 0; i < a; i++) { a = a - 1; } }";
+        assert_eq!(checks_and_bodies(&compile(source, 3)), (3, 3));
+    }
+
+    #[test]
+    fn unroll_keeps_checks_when_the_body_writes_the_loop_variable() {
+        let source = "void f(int &a) { int i; for (i = 0; i < 8; i++) { a = a + i; i = i + 1; } }";
+        assert_eq!(checks_and_bodies(&compile(source, 4)), (4, 4));
+    }
+
+    #[test]
+    fn unroll_gives_each_copy_its_own_labels() {
+        let source = "void f(int &a) { int i; for (i = 0; i < a; i++) { if (a) { a = 1; } } }";
+        let code = compile(source, 3);
+        let mut labels: Vec<&String> = code.iter()
+            .filter_map(|i| match i {
+                Instr::Label(label) => Some(label),
+                _ => None,
+            })
+            .collect();
+        let count = labels.len();
+        labels.sort();
+        labels.dedup();
+        assert_eq!(labels.len(), count);
+        // the loop's start and end, and the if's else and end labels in each copy
+        assert_eq!(count, 2 + 2 * 3);
+    }
+
+    fn errors(source: &str) -> Vec<Diagnostic> {
+        let mut parser = Parser::new(Scanner::new(source.to_owned()), 1);
+        parser.parse().err().expect("test program has errors")
+    }
+
+    #[test]
+    fn missing_semicolon_at_end_of_line_suggests_one() {
+        let errors = errors("void f(int &a) {\n    a = 1\n    a = 2;\n}");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "expected SEMI, found `a`");
+        assert_eq!((errors[0].span.line, errors[0].span.col), (2, 10));
+        assert_eq!(errors[0].suggestions.len(), 1);
+        assert_eq!(errors[0].suggestions[0].replacement, ";");
+    }
+
+    #[test]
+    fn missing_semicolon_before_brace_suggests_one() {
+        let errors = errors("void f(int &a) { a = 1 }");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].suggestions.len(), 1);
+    }
+
+    #[test]
+    fn no_semicolon_suggestion_inside_a_line() {
+        let errors = errors("void f(int &a) {\n    a = a 2;\n}");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "expected SEMI, found `2`");
+        assert_eq!((errors[0].span.line, errors[0].span.col), (2, 11));
+        assert!(errors[0].suggestions.is_empty());
+    }
+
+    // codes of the errors in `source`
+    fn error_codes(source: &str) -> Vec<Code> {
+        errors(source).iter().map(|e| e.code).collect()
+    }
+
+    #[test]
+    fn increment_inside_an_expression() {
+        assert_eq!(error_codes("void f(int &a, int &b) { a = b++; }"), [Code::AssignmentInExpression]);
+        assert_eq!(error_codes("void f(int &a, int &b) { a = b--; }"), [Code::AssignmentInExpression]);
+        assert_eq!(error_codes("void f(int &a, int &b) { a = ++b * 2; }"), [Code::AssignmentInExpression]);
+        assert_eq!(error_codes("void f(int &a, int &b) { a = 1 - --b; }"), [Code::AssignmentInExpression]);
+    }
+
+    #[test]
+    fn assignment_inside_an_expression() {
+        assert_eq!(error_codes("void f(int &a, int &b) { a = (b += 1); }"), [Code::AssignmentInExpression]);
+        assert_eq!(error_codes("void f(int &a, int &b) { a = b = 1; }"), [Code::AssignmentInExpression]);
+
+        let errors = errors("void f(int &a) { if (a = 1) { a = 2; } }");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "`=` can't be used inside an expression");
+        assert!(errors[0].notes.iter().any(|n| n.contains("`==`")));
+        assert!(errors[0].suggestions.is_empty());
+    }
+
+    #[test]
+    fn increments_and_assignments_as_statements() {
+        let code = compile("void f(int &a) { int b; b = 0; b++; ++b; b--; --b; b += 2; b *= 3; a = b; }", 1);
+        let steps = code.iter().filter(|i| matches!(i, Instr::Binary { .. })).count();
+        assert_eq!(steps, 6);
+    }
+
+    #[test]
+    fn bitwise_not_of_a_float_names_the_tilde() {
+        let errors = errors("void f(float &b) { b = ~b; }");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, Code::IntOnlyOperator);
+        assert_eq!(errors[0].message, "invalid operands to `~`");
+        assert_eq!(errors[0].span.col, 24);
+    }
+
+    // the warnings for `source`, which has to compile
+    fn warnings(source: &str) -> Vec<Diagnostic> {
+        let mut parser = Parser::new(Scanner::new(source.to_owned()), 1);
+        parser.parse().expect("test program compiles");
+        parser.warnings()
+    }
+
+    fn warning_codes(source: &str) -> Vec<Code> {
+        warnings(source).iter().map(|w| w.code).collect()
+    }
+
+    #[test]
+    fn constant_overflow_wraps() {
+        // the outer sum uses the wrapped value, so only the inner one overflows
+        let warnings = warnings("void f(int &a) { a = 2147483647 + 1 + 1; }");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, Code::ConstantOverflow);
+        assert_eq!(warnings[0].notes, ["the result wraps to -2147483648"]);
+
+        assert_eq!(warning_codes("void f(int &a) { a = 65536 * 65536; }"), [Code::ConstantOverflow]);
+        assert_eq!(warning_codes("void f(int &a) { a = 1 << 31; }"), [Code::ConstantOverflow]);
+        assert_eq!(warning_codes("void f(int &a) { a = -2147483647 - 1; }"), []);
+    }
+
+    #[test]
+    fn shift_count_out_of_range() {
+        assert_eq!(warning_codes("void f(int &a) { a = 1 << 32; }"), [Code::ConstantOverflow]);
+        assert_eq!(warning_codes("void f(int &a) { a = 1 >> -1; }"), [Code::ConstantOverflow]);
+        assert_eq!(warning_codes("void f(int &a) { a = 1 << 30; }"), []);
+        assert_eq!(warning_codes("void f(int &a) { a = a >> 31; }"), []);
+    }
+
+    #[test]
+    fn int_division_by_a_literal_zero() {
+        assert_eq!(warning_codes("void f(int &a) { a = a / 0; }"), [Code::DivisionByZero]);
+        assert_eq!(warning_codes("void f(int &a) { a = a % 0; }"), [Code::DivisionByZero]);
+        assert_eq!(warning_codes("void f(int &a) { a /= 0; }"), [Code::DivisionByZero]);
+        assert_eq!(warning_codes("void f(float &b) { b = b / 0; }"), []);
+        assert_eq!(warning_codes("void f(float &b) { b = b / 0.0; }"), []);
+    }
+
+    #[test]
+    fn float_equality_skips_not() {
+        assert_eq!(warning_codes("void f(int &a, float &b) { a = b == 0.5; }"), [Code::FloatEquality]);
+        assert_eq!(warning_codes("void f(int &a, float &b) { a = b != a; }"), [Code::FloatEquality]);
+        assert_eq!(warning_codes("void f(int &a, float &b) { a = !b; }"), []);
+        assert_eq!(warning_codes("void f(int &a, float &b) { if (!b) { a = 1; } }"), []);
+    }
+
+    // `a = a + a + ... + a;` with `terms` terms, inside `parens` parentheses
+    fn chain(terms: usize, parens: usize) -> String {
+        let sum = vec!["a"; terms].join(" + ");
+        format!("void f(int &a) {{ a = {}{}{}; }}", "(".repeat(parens), sum, ")".repeat(parens))
+    }
+
+    #[test]
+    fn long_chains_are_too_deep() {
+        compile(&chain(MAX_EXPR_DEPTH, 0), 1);
+        assert_eq!(error_codes(&chain(MAX_EXPR_DEPTH + 1, 0)), [Code::ExpressionTooDeep]);
+        assert_eq!(error_codes(&chain(10000, 0)), [Code::ExpressionTooDeep]);
+    }
+
+    #[test]
+    fn deep_parentheses_are_too_deep() {
+        compile(&chain(1, MAX_NESTING), 1);
+        assert_eq!(error_codes(&chain(1, MAX_NESTING + 1)), [Code::ExpressionTooDeep]);
+        assert_eq!(error_codes(&chain(1, 10000)), [Code::ExpressionTooDeep]);
+        assert_eq!(error_codes(&format!("void f(int &a) {{ a = {}a; }}", "- ".repeat(10000))), [Code::ExpressionTooDeep]);
+    }
+
+    #[test]
+    fn long_assignment_chain_in_an_expression() {
+        let source = format!("void f(int &a) {{ a = {}1; }}", "a = ".repeat(10000));
+        assert_eq!(errors(&source).len(), 10000);
+    }
 }
@@ -1,14 +1,174 @@
 extern crate lazy_static;
 
-use regex::Regex;
+use core::fmt;
 
-pub type Token = (String, String);
+use regex::{Regex, RegexSet};
+
+use crate::ast::Type;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TokenKind {
+    Mul,
+    Plus,
+    Minus,
+    Div,
+    Eq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Ne,
+    Mod,
+    // logical
+    And,
+    Or,
+    Not,
+    // bitwise, `&` is Amp
+    Pipe,
+    Caret,
+    Tilde,
+    Shl,
+    Shr,
+    PlusAssign,
+    MinusAssign,
+    MulAssign,
+    DivAssign,
+    Inc,
+    Dec,
+    LBrace,
+    RBrace,
+    LPar,
+    RPar,
+    Semi,
+    Assign,
+    Amp,
+    Comma,
+    Num,
+    ID,
+    Ignore,
+    // a C keyword C-Mini does not implement
+    Reserved,
+    // keywords
+    If,
+    Else,
+    For,
+    Int,
+    Float,
+    Void,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Mul    => write!(f, "MUL"),
+            TokenKind::Plus   => write!(f, "PLUS"),
+            TokenKind::Minus  => write!(f, "MINUS"),
+            TokenKind::Div    => write!(f, "DIV"),
+            TokenKind::Eq     => write!(f, "EQ"),
+            TokenKind::Lt     => write!(f, "LT"),
+            TokenKind::Gt     => write!(f, "GT"),
+            TokenKind::Le     => write!(f, "LE"),
+            TokenKind::Ge     => write!(f, "GE"),
+            TokenKind::Ne     => write!(f, "NE"),
+            TokenKind::Mod    => write!(f, "MOD"),
+            TokenKind::And    => write!(f, "AND"),
+            TokenKind::Or     => write!(f, "OR"),
+            TokenKind::Not    => write!(f, "NOT"),
+            TokenKind::Pipe   => write!(f, "PIPE"),
+            TokenKind::Caret  => write!(f, "CARET"),
+            TokenKind::Tilde  => write!(f, "TILDE"),
+            TokenKind::Shl    => write!(f, "SHL"),
+            TokenKind::Shr    => write!(f, "SHR"),
+            TokenKind::PlusAssign  => write!(f, "PLUS_ASSIGN"),
+            TokenKind::MinusAssign => write!(f, "MINUS_ASSIGN"),
+            TokenKind::MulAssign   => write!(f, "MUL_ASSIGN"),
+            TokenKind::DivAssign   => write!(f, "DIV_ASSIGN"),
+            TokenKind::Inc    => write!(f, "INC"),
+            TokenKind::Dec    => write!(f, "DEC"),
+            TokenKind::LBrace => write!(f, "LBRACE"),
+            TokenKind::RBrace => write!(f, "RBRACE"),
+            TokenKind::LPar   => write!(f, "LPAR"),
+            TokenKind::RPar   => write!(f, "RPAR"),
+            TokenKind::Semi   => write!(f, "SEMI"),
+            TokenKind::Assign => write!(f, "ASSIGN"),
+            TokenKind::Amp    => write!(f, "AMP"),
+            TokenKind::Comma  => write!(f, "COMMA"),
+            TokenKind::Num    => write!(f, "NUM"),
+            TokenKind::ID     => write!(f, "ID"),
+            TokenKind::Ignore => write!(f, "IGNORE"),
+            TokenKind::Reserved => write!(f, "RESERVED"),
+            TokenKind::If     => write!(f, "IF"),
+            TokenKind::Else   => write!(f, "ELSE"),
+            TokenKind::For    => write!(f, "FOR"),
+            TokenKind::Int    => write!(f, "INT"),
+            TokenKind::Float  => write!(f, "FLOAT"),
+            TokenKind::Void   => write!(f, "VOID"),
+        }
+    }
+}
+
+// where a piece of source text is, lines and columns count from 1
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Span {
+    // byte offsets of the first character and one past the last
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub lexeme: String,
+    pub span: Span,
+    // the value of a NUM, which also tells int and float literals apart
+    pub value: Option<Type>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum LexError {
+    // a character no token starts with
+    UnexpectedChar { ch: char, span: Span },
+    // a `/*` with no `*/` after it, the span covers the opening `/*`
+    UnterminatedComment { span: Span },
+    // a number that does not follow C's literal grammar, like `09` or `1e`
+    InvalidNumber { lexeme: String, span: Span },
+    // an integer literal that does not fit an int, or a float literal that
+    // does not fit a float
+    NumberOutOfRange { lexeme: String, span: Span },
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnexpectedChar { span, .. } |
+            LexError::UnterminatedComment { span } |
+            LexError::InvalidNumber { span, .. } |
+            LexError::NumberOutOfRange { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::UnexpectedChar { ch, .. } => write!(f, "unexpected character {:?}", ch),
+            LexError::UnterminatedComment { .. } => write!(f, "unterminated block comment"),
+            LexError::InvalidNumber { lexeme, .. } => write!(f, "invalid numeric literal `{}`", lexeme),
+            LexError::NumberOutOfRange { lexeme, .. } => write!(f, "numeric literal `{}` is out of range", lexeme),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+type TokenFn = fn(TokenKind, &str) -> TokenKind;
 
 pub struct Scanner {
-    pub lineno: i32,
+    pub lineno: usize,
     off: usize,
-    // tokens: Vec<(&'static str, Regex, &'static fn(Token) -> Box<Token>)>,
-    // tokens: &'static [(&'static str, Regex, &'staticfn(Token) -> Box<Token>); 16],
+    line_start: usize,
     istring: String,
 }
 
@@ -17,118 +177,305 @@ impl Scanner {
         Scanner {
             lineno: 1,
             off: 0,
-            // tokens: Vec::from(&TOKENS),
+            line_start: 0,
             istring
         }
     }
 
-    pub fn token(&mut self) -> Option<Token> {
+    // the position the scanner has reached, as an empty span
+    pub fn position(&self) -> Span {
+        Span {
+            start: self.off,
+            end: self.off,
+            line: self.lineno,
+            col: self.off - self.line_start + 1,
+        }
+    }
+
+    // consumes `len` bytes of input, keeping track of the lines passed
+    fn advance(&mut self, len: usize) {
+        let consumed = &self.istring[self.off..self.off + len];
+        if let Some(last) = consumed.rfind('\n') {
+            self.lineno += consumed.matches('\n').count();
+            self.line_start = self.off + last + 1;
+        }
+        self.off += len;
+    }
+
+    // the span of the next `len` bytes of input
+    fn span_of(&self, len: usize) -> Span {
+        Span {
+            end: self.off + len,
+            ..self.position()
+        }
+    }
+
+    // maximal munch: every pattern is anchored at the current offset, the
+    // RegexSet finds the ones that match in a single pass and the longest
+    // match wins, ties going to the pattern listed first in TOKENS.
+    // A character that starts no token is skipped and returned as an error,
+    // so the next call carries on after it
+    pub fn token(&mut self) -> Option<Result<Token, LexError>> {
         loop {
-            if self.off >= (self.istring.len() - 1) {
+            if self.off >= self.istring.len() {
                 return None;
             }
 
-            let mut matches = Vec::new();
-
-            // loop through substrings
-            let mut i = self.istring.len() - 1;
-            // println!("i starting at {i}");
-            while i > self.off {
-                matches = Vec::new();
-
-                for t in TOKENS.iter() {
-                    let m = t.1.captures(&self.istring[self.off..i])
-                            .map_or("", |m| {
-                                m.get(0).map_or("", |x| x.as_str())
-                            });
-                    if m.len() > 0 {
-                        matches.push((t.0, m, t.2));
-                    }
-                }
+            let rest = &self.istring[self.off..];
 
-                if matches.len() > 0 {
-                    break;
+            // block comments are skipped by hand so a missing `*/` can be
+            // reported where the comment was opened
+            if let Some(comment) = rest.strip_prefix("/*") {
+                let span = self.span_of(2);
+                match comment.find("*/") {
+                    Some(i) => self.advance(i + 4),
+                    None => {
+                        self.advance(rest.len());
+                        return Some(Err(LexError::UnterminatedComment { span }));
+                    },
                 }
-
-                i -= 1;
+                continue;
             }
 
-            if matches.len() == 0 {
-                // TODO raise error
-                println!("Error no matches found");
-                std::process::exit(1);
+            let mut longest: Option<(usize, &str)> = None;
+            for i in TOKEN_SET.matches(rest).into_iter() {
+                let m = TOKENS[i].1.find(rest).map_or("", |m| m.as_str());
+                if longest.is_none_or(|(_, l)| m.len() > l.len()) {
+                    longest = Some((i, m));
+                }
             }
-            matches.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
-            // println!("matches: {:?}", matches);
-            let longest = matches[0];
-            let lexeme = longest.2((longest.0.to_string(), longest.1.to_string()));
-            // println!("lex: {:?}", lexeme);
-
-            self.off += lexeme.1.len();
-            // println!("off: {:?}", self.off);
-
-            // println!("remaining string: {:?}", &self.istring[self.off..]);
-
-            if lexeme.0 != "IGNORE" {
-                return Some(*lexeme);
-            } else {
-                if *lexeme.1 == *"\n" {
-                    self.lineno += 1;
+
+            let (i, m) = match longest {
+                Some(l) => l,
+                None => {
+                    let ch = rest.chars().next().unwrap_or_default();
+                    let span = self.span_of(ch.len_utf8());
+                    self.advance(ch.len_utf8());
+                    return Some(Err(LexError::UnexpectedChar { ch, span }));
                 }
+            };
+            let t = &TOKENS[i];
+            let mut tok = Token {
+                kind: t.2(t.0, m),
+                lexeme: m.to_string(),
+                span: self.span_of(m.len()),
+                value: None,
+            };
+
+            self.advance(m.len());
+
+            if tok.kind == TokenKind::Num {
+                match number_value(&tok.lexeme) {
+                    Ok(value) => tok.value = Some(value),
+                    Err(out_of_range) => {
+                        let (lexeme, span) = (tok.lexeme, tok.span);
+                        return Some(Err(if out_of_range {
+                            LexError::NumberOutOfRange { lexeme, span }
+                        } else {
+                            LexError::InvalidNumber { lexeme, span }
+                        }));
+                    },
+                }
+            }
+
+            if tok.kind != TokenKind::Ignore {
+                return Some(Ok(tok));
             }
         }
     }
 }
 
 lazy_static! {
-    static ref TOKENS: [(&'static str, Regex, &'static fn(Token) -> Box<Token>); 17] =
-    [  ("MUL",    Regex::new(r"^\*$").unwrap(),                             &IDY)
-    ,  ("PLUS",   Regex::new(r"^\+$").unwrap(),                             &IDY)
-    ,  ("MINUS",  Regex::new(r"^-$").unwrap(),                              &IDY)
-    ,  ("DIV",    Regex::new(r"^/$").unwrap(),                              &IDY)
-    ,  ("EQ",     Regex::new(r"^==$").unwrap(),                             &IDY)
-    ,  ("LT",     Regex::new(r"^<$").unwrap(),                              &IDY)
-    ,  ("LBRACE", Regex::new(r"^\{$").unwrap(),                             &IDY)
-    ,  ("RBRACE", Regex::new(r"^\}$").unwrap(),                             &IDY)
-    ,  ("LPAR",   Regex::new(r"^\($").unwrap(),                             &IDY)
-    ,  ("RPAR",   Regex::new(r"^\)$").unwrap(),                             &IDY)
-    ,  ("SEMI",   Regex::new(r"^;$").unwrap(),                              &IDY)
-    ,  ("ASSIGN", Regex::new(r"^=$").unwrap(),                              &IDY)
-    ,  ("AMP",    Regex::new(r"^&$").unwrap(),                              &IDY)
-    ,  ("COMMA",  Regex::new(r"^,$").unwrap(),                              &IDY)
-    ,  ("NUM",    Regex::new(r"^([0-9]+(\.[0-9]+)?)|(\.[0-9]+)$").unwrap(), &IDY)
-    ,  ("ID",     Regex::new(r"^[a-zA-Z]+[a-zA-Z0-9]*$").unwrap(),          &FIND_KEYWORDS)
-    ,  ("IGNORE", Regex::new(r"^[ \n\t]$").unwrap(),                        &IDY)
+    static ref TOKENS: [(TokenKind, Regex, &'static TokenFn); 37] =
+    [  (TokenKind::Mul,    Regex::new(r"^\*").unwrap(),                              &IDY)
+    ,  (TokenKind::Plus,   Regex::new(r"^\+").unwrap(),                              &IDY)
+    ,  (TokenKind::Minus,  Regex::new(r"^-").unwrap(),                               &IDY)
+    ,  (TokenKind::Div,    Regex::new(r"^/").unwrap(),                               &IDY)
+    ,  (TokenKind::Eq,     Regex::new(r"^==").unwrap(),                              &IDY)
+    ,  (TokenKind::Lt,     Regex::new(r"^<").unwrap(),                               &IDY)
+    ,  (TokenKind::Gt,     Regex::new(r"^>").unwrap(),                               &IDY)
+    ,  (TokenKind::Le,     Regex::new(r"^<=").unwrap(),                              &IDY)
+    ,  (TokenKind::Ge,     Regex::new(r"^>=").unwrap(),                              &IDY)
+    ,  (TokenKind::Ne,     Regex::new(r"^!=").unwrap(),                              &IDY)
+    ,  (TokenKind::Mod,    Regex::new(r"^%").unwrap(),                               &IDY)
+    ,  (TokenKind::And,    Regex::new(r"^&&").unwrap(),                              &IDY)
+    ,  (TokenKind::Or,     Regex::new(r"^\|\|").unwrap(),                            &IDY)
+    ,  (TokenKind::Not,    Regex::new(r"^!").unwrap(),                               &IDY)
+    ,  (TokenKind::Pipe,   Regex::new(r"^\|").unwrap(),                              &IDY)
+    ,  (TokenKind::Caret,  Regex::new(r"^\^").unwrap(),                              &IDY)
+    ,  (TokenKind::Tilde,  Regex::new(r"^~").unwrap(),                               &IDY)
+    ,  (TokenKind::Shl,    Regex::new(r"^<<").unwrap(),                              &IDY)
+    ,  (TokenKind::Shr,    Regex::new(r"^>>").unwrap(),                              &IDY)
+    ,  (TokenKind::PlusAssign,  Regex::new(r"^\+=").unwrap(),                        &IDY)
+    ,  (TokenKind::MinusAssign, Regex::new(r"^-=").unwrap(),                         &IDY)
+    ,  (TokenKind::MulAssign,   Regex::new(r"^\*=").unwrap(),                        &IDY)
+    ,  (TokenKind::DivAssign,   Regex::new(r"^/=").unwrap(),                         &IDY)
+    ,  (TokenKind::Inc,    Regex::new(r"^\+\+").unwrap(),                            &IDY)
+    ,  (TokenKind::Dec,    Regex::new(r"^--").unwrap(),                              &IDY)
+    ,  (TokenKind::LBrace, Regex::new(r"^\{").unwrap(),                              &IDY)
+    ,  (TokenKind::RBrace, Regex::new(r"^\}").unwrap(),                              &IDY)
+    ,  (TokenKind::LPar,   Regex::new(r"^\(").unwrap(),                              &IDY)
+    ,  (TokenKind::RPar,   Regex::new(r"^\)").unwrap(),                              &IDY)
+    ,  (TokenKind::Semi,   Regex::new(r"^;").unwrap(),                               &IDY)
+    ,  (TokenKind::Assign, Regex::new(r"^=").unwrap(),                               &IDY)
+    ,  (TokenKind::Amp,    Regex::new(r"^&").unwrap(),                               &IDY)
+    ,  (TokenKind::Comma,  Regex::new(r"^,").unwrap(),                               &IDY)
+    ,  (TokenKind::Num,    Regex::new(r"^\.?[0-9]([eEpP][+-]|[0-9a-zA-Z_.])*").unwrap(), &IDY)
+    ,  (TokenKind::ID,     Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*").unwrap(),          &FIND_KEYWORDS)
+    ,  (TokenKind::Ignore, Regex::new(r"^[ \r\n\t]").unwrap(),                       &IDY)
+    ,  (TokenKind::Ignore, Regex::new(r"^//[^\n]*").unwrap(),                        &IDY)
     ];
+
+    static ref TOKEN_SET: RegexSet = RegexSet::new(TOKENS.iter().map(|t| t.1.as_str())).unwrap();
+
+    // the forms a NUM can take once it has been scanned
+    static ref INT_LITERAL: Regex =
+        Regex::new(r"^(0[xX][0-9a-fA-F]+|0[0-7]*|[1-9][0-9]*)([uU](ll|LL|l|L)?|(ll|LL|l|L)[uU]?)?$").unwrap();
+    static ref DEC_FLOAT_LITERAL: Regex =
+        Regex::new(r"^(([0-9]*\.[0-9]+|[0-9]+\.)([eE][+-]?[0-9]+)?|[0-9]+[eE][+-]?[0-9]+)[fFlL]?$").unwrap();
+    static ref HEX_FLOAT_LITERAL: Regex =
+        Regex::new(r"^0[xX]([0-9a-fA-F]*)(\.([0-9a-fA-F]*))?[pP]([+-]?[0-9]+)[fFlL]?$").unwrap();
 }
 
-static KEYWORDS: [(&str, &str); 6] = [
-    ("IF", "if"),
-    ("ELSE", "else"),
-    ("FOR", "for"),
-    ("INT", "int"),
-    ("FLOAT", "float"),
-    ("VOID", "void"),
+static KEYWORDS: [(TokenKind, &str); 6] = [
+    (TokenKind::If, "if"),
+    (TokenKind::Else, "else"),
+    (TokenKind::For, "for"),
+    (TokenKind::Int, "int"),
+    (TokenKind::Float, "float"),
+    (TokenKind::Void, "void"),
 ];
 
-fn idy(t: Token) -> Box<Token> {
-    Box::new(t)
+fn idy(kind: TokenKind, _lexeme: &str) -> TokenKind {
+    kind
 }
 
-const IDY: fn(Token) -> Box<Token> = idy;
-
-// TODO change token type to str, str
-fn find_keywords(t: Token) -> Box<Token> {
-    // println!("finding: {:?}", t);
-    Box::new(
-        KEYWORDS
-            .iter()
-            .find(|e| e.1 == t.1)
-            .and_then(|e| {
-                Some((e.0.to_string(), e.1.to_string()))
-            })
-            .unwrap_or(t)
-    )
+const IDY: TokenFn = idy;
+
+// the rest of C11's keywords, which can't be used as names either
+static RESERVED: [&str; 38] = [
+    "auto", "break", "case", "char", "const", "continue", "default", "do",
+    "double", "enum", "extern", "goto", "inline", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct",
+    "switch", "typedef", "union", "unsigned", "volatile", "while",
+    "_Alignas", "_Alignof", "_Atomic", "_Bool", "_Complex", "_Generic",
+    "_Imaginary", "_Noreturn", "_Static_assert", "_Thread_local",
+];
+
+fn find_keywords(kind: TokenKind, lexeme: &str) -> TokenKind {
+    if RESERVED.contains(&lexeme) {
+        return TokenKind::Reserved;
+    }
+    KEYWORDS
+        .iter()
+        .find(|e| e.1 == lexeme)
+        .map(|e| e.0)
+        .unwrap_or(kind)
 }
 
-const FIND_KEYWORDS: fn(Token) -> Box<Token> = find_keywords;
+const FIND_KEYWORDS: TokenFn = find_keywords;
+
+// NUM is scanned like a C preprocessing number, so `0x1e+5` or `09` come out
+// as a single token, and is only checked against the literal grammar here.
+// The error is true when the literal is well formed but out of range
+fn number_value(lexeme: &str) -> Result<Type, bool> {
+    if let Some(caps) = INT_LITERAL.captures(lexeme) {
+        let digits = &caps[1];
+        let value = if digits.len() > 2 && digits[..2].eq_ignore_ascii_case("0x") {
+            i32::from_str_radix(&digits[2..], 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            i32::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse()
+        };
+        // the only way a digit string matching the grammar fails to parse
+        return value.map(Type::Int).map_err(|_| true);
+    }
+
+    let value = if DEC_FLOAT_LITERAL.is_match(lexeme) {
+        lexeme.trim_end_matches(['f', 'F', 'l', 'L']).parse::<f32>().map_err(|_| false)?
+    } else if let Some(caps) = HEX_FLOAT_LITERAL.captures(lexeme) {
+        let whole = &caps[1];
+        let frac = caps.get(3).map_or("", |m| m.as_str());
+        if whole.is_empty() && frac.is_empty() {
+            return Err(false);
+        }
+        let mantissa = whole.chars().chain(frac.chars())
+            .fold(0.0, |m: f64, d| m * 16.0 + d.to_digit(16).unwrap_or(0) as f64);
+        let exp: i32 = caps[4].parse().unwrap_or(i32::MAX);
+        (mantissa * 2f64.powi(exp.saturating_sub(4 * frac.len() as i32))) as f32
+    } else {
+        return Err(false);
+    };
+
+    if value.is_finite() {
+        Ok(Type::Float(value))
+    } else {
+        Err(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_literals() {
+        assert_eq!(number_value("0"), Ok(Type::Int(0)));
+        assert_eq!(number_value("42"), Ok(Type::Int(42)));
+        assert_eq!(number_value("017"), Ok(Type::Int(15)));
+        assert_eq!(number_value("0x1F"), Ok(Type::Int(31)));
+        assert_eq!(number_value("0XfF"), Ok(Type::Int(255)));
+        assert_eq!(number_value("10u"), Ok(Type::Int(10)));
+        assert_eq!(number_value("10UL"), Ok(Type::Int(10)));
+        assert_eq!(number_value("0x10ll"), Ok(Type::Int(16)));
+        assert_eq!(number_value("2147483647"), Ok(Type::Int(i32::MAX)));
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(number_value("1.5"), Ok(Type::Float(1.5)));
+        assert_eq!(number_value("1."), Ok(Type::Float(1.0)));
+        assert_eq!(number_value(".25"), Ok(Type::Float(0.25)));
+        assert_eq!(number_value("1e3"), Ok(Type::Float(1000.0)));
+        assert_eq!(number_value("2.5E-1"), Ok(Type::Float(0.25)));
+        assert_eq!(number_value("1e+2f"), Ok(Type::Float(100.0)));
+        assert_eq!(number_value("3.0L"), Ok(Type::Float(3.0)));
+        assert_eq!(number_value("0x1p4"), Ok(Type::Float(16.0)));
+        assert_eq!(number_value("0x.8p1"), Ok(Type::Float(1.0)));
+        assert_eq!(number_value("0x1.8P-1f"), Ok(Type::Float(0.75)));
+    }
+
+    #[test]
+    fn invalid_literals() {
+        assert_eq!(number_value("09"), Err(false));
+        assert_eq!(number_value("1e"), Err(false));
+        assert_eq!(number_value("1e+"), Err(false));
+        assert_eq!(number_value("0x"), Err(false));
+        assert_eq!(number_value("0x1.8"), Err(false));
+        assert_eq!(number_value("1.5u"), Err(false));
+        assert_eq!(number_value("12abc"), Err(false));
+    }
+
+    #[test]
+    fn out_of_range_literals() {
+        assert_eq!(number_value("2147483648"), Err(true));
+        assert_eq!(number_value("0x100000000"), Err(true));
+        assert_eq!(number_value("1e39"), Err(true));
+        assert_eq!(number_value("0x1p128"), Err(true));
+    }
+
+    #[test]
+    fn malformed_number_is_one_token() {
+        let mut scanner = Scanner::new("a = 09;".to_owned());
+        let tokens: Vec<Result<TokenKind, String>> = std::iter::from_fn(|| scanner.token())
+            .map(|t| t.map(|t| t.kind).map_err(|e| e.to_string()))
+            .collect();
+        assert_eq!(tokens, [
+            Ok(TokenKind::ID),
+            Ok(TokenKind::Assign),
+            Err("invalid numeric literal `09`".to_owned()),
+            Ok(TokenKind::Semi),
+        ]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::codes::Code;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::ir::{basic_blocks, Instr, Operand};
+use crate::scanner::Span;
+
+// a local or IO argument as it was declared
+pub struct Declaration {
+    pub name: String,
+    // what the IR calls it
+    pub operand: Operand,
+    pub span: Span,
+}
+
+// the source spans of the variables an instruction reads
+pub type Reads = Vec<(Operand, Span)>;
+
+// warnings about the variables of a lowered function. `reads` holds the
+// spans read by each instruction of `code`, in the same order
+pub fn check(code: &[Instr], reads: &[Reads], declarations: &[Declaration]) -> Vec<Diagnostic> {
+    let mut warnings = unused(code, declarations);
+    warnings.extend(read_before_assignment(code, reads, declarations));
+
+    warnings
+}
+
+// locals that are never read, and IO arguments that are neither read nor written
+fn unused(code: &[Instr], declarations: &[Declaration]) -> Vec<Diagnostic> {
+    let read: HashSet<String> = code.iter().flat_map(|i| i.reads()).map(key).collect();
+    let written: HashSet<String> = code.iter().filter_map(|i| i.writes()).map(key).collect();
+
+    let mut warnings = Vec::new();
+    for d in declarations.iter() {
+        let k = key(&d.operand);
+        match d.operand {
+            Operand::Var(_) if !read.contains(&k) => {
+                let mut warning = Diagnostic::new(Severity::Warning, Code::UnusedVariable, format!("unused variable `{}`", d.name), d.span)
+                    .with_label("declared here but never read");
+                if written.contains(&k) {
+                    warning = warning.with_note("it is assigned, but the value is never used");
+                }
+                warnings.push(warning);
+            },
+            Operand::IO(_) if !read.contains(&k) && !written.contains(&k) => {
+                warnings.push(Diagnostic::new(Severity::Warning, Code::UnusedParameter, format!("parameter `{}` is never read or written", d.name), d.span)
+                    .with_label("unused parameter"));
+            },
+            _ => {},
+        }
+    }
+
+    warnings
+}
+
+// definite assignment: a local is assigned at a point when every path from
+// the start of the function to that point assigns it. A read of a local
+// that isn't is reported, once per local
+fn read_before_assignment(code: &[Instr], reads: &[Reads], declarations: &[Declaration]) -> Vec<Diagnostic> {
+    let blocks = basic_blocks(code);
+    let label_block: HashMap<&str, usize> = blocks.iter().enumerate()
+        .filter_map(|(b, r)| match &code[r.start] {
+            Instr::Label(label) => Some((label.as_str(), b)),
+            _ => None,
+        })
+        .collect();
+    let successors: Vec<Vec<usize>> = blocks.iter().enumerate()
+        .map(|(b, r)| {
+            let next = (b + 1 < blocks.len()).then_some(b + 1);
+            match &code[r.end - 1] {
+                Instr::Branch(label) => label_block.get(label.as_str()).copied().into_iter().collect(),
+                Instr::Beq { label, .. } |
+                Instr::Bne { label, .. } => label_block.get(label.as_str()).copied().into_iter().chain(next).collect(),
+                _ => next.into_iter().collect(),
+            }
+        })
+        .collect();
+
+    // locals assigned on entry to each block, None until a path reaches it
+    let mut assigned: Vec<Option<HashSet<String>>> = vec![None; blocks.len()];
+    if let Some(entry) = assigned.first_mut() {
+        *entry = Some(HashSet::new());
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (b, r) in blocks.iter().enumerate() {
+            let Some(mut out) = assigned[b].clone() else {
+                continue;
+            };
+            out.extend(code[r.clone()].iter().filter_map(|i| i.writes()).filter_map(local));
+            for &s in successors[b].iter() {
+                let merged = match &assigned[s] {
+                    Some(before) => before.intersection(&out).cloned().collect(),
+                    None => out.clone(),
+                };
+                if assigned[s].as_ref() != Some(&merged) {
+                    assigned[s] = Some(merged);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut warnings = Vec::new();
+    let mut reported = HashSet::new();
+    for (b, r) in blocks.iter().enumerate() {
+        let Some(mut now) = assigned[b].clone() else {
+            continue;
+        };
+        for (instr, spans) in code[r.clone()].iter().zip(reads[r.clone()].iter()) {
+            for operand in instr.reads() {
+                let Some(var) = local(operand) else {
+                    continue;
+                };
+                if now.contains(&var) || !reported.insert(var.clone()) {
+                    continue;
+                }
+                let Some(d) = declarations.iter().find(|d| d.operand == *operand) else {
+                    continue;
+                };
+                let span = spans.iter().find(|(o, _)| o == operand).map_or(d.span, |(_, span)| *span);
+                warnings.push(Diagnostic::new(Severity::Warning, Code::ReadBeforeAssignment, format!("`{}` may be read before it is assigned", d.name), span)
+                    .with_label("read here")
+                    .with_secondary(d.span, "declared here")
+                    .with_note("on at least one path to this read the variable is never assigned"));
+            }
+            if let Some(var) = instr.writes().and_then(local) {
+                now.insert(var);
+            }
+        }
+    }
+
+    warnings
+}
+
+fn key(operand: &Operand) -> String {
+    format!("{:?}", operand)
+}
+
+fn local(operand: &Operand) -> Option<String> {
+    match operand {
+        Operand::Var(name) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codes::Code;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    // the code and line of every warning for `source`
+    fn warnings(source: &str) -> Vec<(Code, usize)> {
+        let mut parser = Parser::new(Scanner::new(source.to_owned()), 1);
+        parser.parse().expect("test program compiles");
+        parser.warnings().iter().map(|w| (w.code, w.span.line)).collect()
+    }
+
+    #[test]
+    fn unused_locals_and_parameters() {
+        let source = "\
+void f(int &a, int &b) {
+    int x;
+    int y;
+    x = 1;
+    y = 2;
+    a = y;
+}";
+        assert_eq!(warnings(source), [(Code::UnusedParameter, 1), (Code::UnusedVariable, 2)]);
+    }
+
+    #[test]
+    fn read_before_assignment_points_at_the_offending_read() {
+        // both reads lower to the same instruction, only the second is unassigned
+        let source = "\
+void f(int &a, int &c) {
+    int x;
+    if (c) {
+        x = 1;
+        a = x;
+    }
+    a = x;
+}";
+        assert_eq!(warnings(source), [(Code::ReadBeforeAssignment, 7)]);
+    }
+
+    #[test]
+    fn assignment_on_every_path_counts() {
+        let source = "\
+void f(int &a, int &c) {
+    int x;
+    if (c) {
+        x = 1;
+    } else {
+        x = 2;
+    }
+    a = x;
+}";
+        assert_eq!(warnings(source), []);
+    }
+
+    #[test]
+    fn loop_carried_read_is_reported() {
+        let source = "\
+void f(int &a) {
+    int i;
+    int w;
+    for (i = 0; i < a; i++) {
+        if (w) {
+            a = 1;
+        }
+        w = 1;
+    }
+}";
+        assert_eq!(warnings(source), [(Code::ReadBeforeAssignment, 5)]);
+    }
+}